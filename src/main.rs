@@ -3,20 +3,46 @@
 
 slint::include_modules!();
 
+mod pwm_cli;
 mod pwm_gui_data;
+mod pwm_passphrase;
 mod pwm_settings;
-use crate::pwm_gui_data::{master_verification, PwmConfigError, PwmGui, PwmGuiData};
-use crate::pwm_settings::{PwmSetting, PWM_DEFAULT};
+mod pwm_strength;
+use crate::pwm_gui_data::{
+    charset_bias_text, charset_display, clipboard_available, copy_to_clipboard, derive_master,
+    enum_names_parse, extract_domain, format_output, generate_password, generation_characters,
+    is_probably_url, leet_config_ok, master_quality, master_verification, unsupported_algorithms,
+    ConfigFormat, ImportMode, PwmConfigError, PwmGui, PwmGuiData,
+};
+use crate::pwm_passphrase::{DICE_CHARACTERS, ROLLS_PER_WORD};
+use crate::pwm_settings::{
+    url_with_flags, ConflictDecision, FieldChange, KdfConfig, PwmSetting, PwmSettings,
+    PwmSettingsError, SearchScope, SettingField, UrlFlag, UrlFlags, LENGTH_PRESETS,
+    MAX_PASSWORD_LENGTH, PAGE_MAKE, PWM_DEFAULT,
+};
+use crate::pwm_strength::{
+    character_histogram, crack_time_string, estimate_entropy_bits, foreign_characters,
+    min_charset_string, risky_charset_chars, DEFAULT_GUESSES_PER_SECOND,
+};
 
 use once_cell::sync::Lazy;
-use std::{rc::Rc, sync::Mutex, vec::Vec};
+use std::{
+    io::IsTerminal,
+    rc::Rc,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Mutex,
+    },
+    thread,
+    vec::Vec,
+};
 use strum::VariantNames;
 
-use slint::{ModelRc, SharedString, VecModel};
+use slint::{Image, Model, ModelRc, Rgba8Pixel, SharedPixelBuffer, SharedString, VecModel};
 
-use passwordmaker_rs::{
-    HashAlgorithm, LeetLevel, ProtocolUsageMode, UrlParsing, UseLeetWhenGenerating,
-};
+use passwordmaker_rs::{HashAlgorithm, LeetLevel, UseLeetWhenGenerating};
 
 // Model data has static life time, must exist as long as the app, accessible from callbacks
 static PWM_DATA: Lazy<Mutex<PwmGuiData>> = Lazy::new(|| {
@@ -26,53 +52,213 @@ static PWM_DATA: Lazy<Mutex<PwmGuiData>> = Lazy::new(|| {
     })
 });
 
+// number of the latest generation request, see on_used_text_edited
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// everything the worker needs to generate without PWM_DATA
+struct GenerationRequest {
+    number: u64,
+    app: slint::Weak<App>,
+    setting: PwmSetting,
+    url: String,
+    master: String,
+    kdf: Option<KdfConfig>,
+}
+
+// a single worker, started with the first request, so typing fast queues
+// requests instead of starting a thread per key
+static GENERATION_WORKER: Lazy<Mutex<Sender<GenerationRequest>>> = Lazy::new(|| {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || generation_worker(receiver));
+    Mutex::new(sender)
+});
+
+// settings saved by on_snapshot for the session, separate from the undo history
+// and never written to disk
+static SNAPSHOT: Mutex<Option<PwmSettings>> = Mutex::new(None);
+
 // rust slint type conversion
+// Both sides are destructured without `..`, a field added to either struct
+// fails to compile here until it is mapped.
 impl From<PwmSlintSetting> for PwmSetting {
     fn from(item: PwmSlintSetting) -> PwmSetting {
+        let PwmSlintSetting {
+            name,
+            hash_algorithm,
+            use_leet,
+            leet_level,
+            characters,
+            username,
+            modifier,
+            password_length,
+            prefix,
+            suffix,
+            use_domain,
+            use_subdomain,
+            use_protocol,
+            use_params,
+            use_userinfo,
+            excluded_characters,
+            notes,
+            modifier_date_format,
+            max_output_length,
+            passphrase_mode,
+            passphrase_words,
+            passphrase_separator,
+            separator,
+            output_template,
+            charset_file,
+            use_username,
+            force_case,
+            turkish_case,
+            trim_url,
+            rotate_days,
+            url_generation_overrides,
+        } = item;
         PwmSetting {
-            name: item.name.into(),
-            hash_algorithm: item.hash_algorithm.into(),
-            use_leet: item.use_leet.into(),
-            leet_level: item.leet_level.into(),
-            characters: item.characters.into(),
-            username: item.username.into(),
-            modifier: item.modifier.into(),
-            password_length: match item.password_length.try_into() {
+            name: name.into(),
+            hash_algorithm: hash_algorithm.into(),
+            use_leet: use_leet.into(),
+            leet_level: leet_level.into(),
+            characters: characters.into(),
+            username: username.into(),
+            modifier: modifier.into(),
+            // a zero length can't generate, use the default for unusable values
+            password_length: match password_length.try_into() {
+                Ok(0) | Err(_) => PWM_DEFAULT.password_length,
                 Ok(pwl) => pwl,
-                Err(_) => 0,
             },
-            prefix: item.prefix.into(),
-            suffix: item.suffix.into(),
-            use_domain: item.use_domain,
-            use_subdomain: item.use_subdomain,
-            use_protocol: item.use_protocol,
-            use_params: item.use_params,
-            use_userinfo: item.use_userinfo,
+            prefix: prefix.into(),
+            suffix: suffix.into(),
+            use_domain,
+            use_subdomain,
+            use_protocol,
+            use_params,
+            use_userinfo,
+            excluded_characters: excluded_characters.into(),
+            notes: notes.into(),
+            modifier_date_format: match modifier_date_format.as_str() {
+                "" => None,
+                date_format => Some(date_format.into()),
+            },
+            // kept from the stored setting by set_current_setting_data
+            created: None,
+            canary: None,
+            max_output_length: match max_output_length.try_into() {
+                Ok(0) | Err(_) => None,
+                Ok(max) => Some(max),
+            },
+            passphrase_mode,
+            // no words can't generate either
+            passphrase_words: match passphrase_words.try_into() {
+                Ok(0) | Err(_) => PWM_DEFAULT.passphrase_words,
+                Ok(words) => words,
+            },
+            passphrase_separator: passphrase_separator.into(),
+            separator: separator.into(),
+            output_template: match output_template.as_str() {
+                "" => None,
+                template => Some(template.into()),
+            },
+            charset_file: match charset_file.as_str() {
+                "" => None,
+                path => Some(path.into()),
+            },
+            use_username,
+            force_case: force_case.into(),
+            turkish_case,
+            trim_url,
+            rotate_days: match rotate_days.try_into() {
+                Ok(0) | Err(_) => None,
+                Ok(days) => Some(days),
+            },
+            // empty or malformed digits mean no overrides
+            url_generation_overrides: UrlFlags::from_digits(url_generation_overrides.as_str()),
         }
     }
 }
 
 impl From<PwmSetting> for PwmSlintSetting {
     fn from(item: PwmSetting) -> PwmSlintSetting {
+        let PwmSetting {
+            name,
+            hash_algorithm,
+            use_leet,
+            leet_level,
+            characters,
+            username,
+            modifier,
+            password_length,
+            prefix,
+            suffix,
+            use_domain,
+            use_subdomain,
+            use_protocol,
+            use_params,
+            use_userinfo,
+            excluded_characters,
+            notes,
+            modifier_date_format,
+            created: _,
+            canary: _,
+            max_output_length,
+            passphrase_mode,
+            passphrase_words,
+            passphrase_separator,
+            separator,
+            output_template,
+            charset_file,
+            use_username,
+            force_case,
+            turkish_case,
+            trim_url,
+            rotate_days,
+            url_generation_overrides,
+        } = item;
         PwmSlintSetting {
-            name: item.name.into(),
-            hash_algorithm: item.hash_algorithm.into(),
-            use_leet: item.use_leet.into(),
-            leet_level: item.leet_level.into(),
-            characters: item.characters.into(),
-            username: item.username.into(),
-            modifier: item.modifier.into(),
-            password_length: match item.password_length.try_into() {
+            name: name.into(),
+            hash_algorithm: hash_algorithm.into(),
+            use_leet: use_leet.into(),
+            leet_level: leet_level.into(),
+            characters: characters.into(),
+            username: username.into(),
+            modifier: modifier.into(),
+            password_length: match password_length.try_into() {
                 Ok(pwl) => pwl,
                 Err(_) => 0,
             },
-            prefix: item.prefix.into(),
-            suffix: item.suffix.into(),
-            use_domain: item.use_domain,
-            use_subdomain: item.use_subdomain,
-            use_protocol: item.use_protocol,
-            use_params: item.use_params,
-            use_userinfo: item.use_userinfo,
+            prefix: prefix.into(),
+            suffix: suffix.into(),
+            use_domain,
+            use_subdomain,
+            use_protocol,
+            use_params,
+            use_userinfo,
+            excluded_characters: excluded_characters.into(),
+            notes: notes.into(),
+            modifier_date_format: modifier_date_format.unwrap_or_default().into(),
+            max_output_length: match max_output_length {
+                Some(max) => max.try_into().unwrap_or(0),
+                None => 0,
+            },
+            passphrase_mode,
+            passphrase_words: passphrase_words.try_into().unwrap_or(0),
+            passphrase_separator: passphrase_separator.into(),
+            separator: separator.into(),
+            output_template: output_template.unwrap_or_default().into(),
+            charset_file: charset_file.unwrap_or_default().into(),
+            use_username,
+            force_case: force_case.into(),
+            turkish_case,
+            trim_url,
+            rotate_days: match rotate_days {
+                Some(days) => days.try_into().unwrap_or(0),
+                None => 0,
+            },
+            url_generation_overrides: url_generation_overrides
+                .map(|flags| flags.to_digits())
+                .unwrap_or_default()
+                .into(),
         }
     }
 }
@@ -83,27 +269,108 @@ fn on_url_edited(url: SharedString) -> SharedString {
         Ok(pwm) => pwm,
         Err(_) => return SharedString::from("No Lock!"),
     };
-    let use_protocol = if pwm.get_current_setting_data().use_protocol {
-        ProtocolUsageMode::Used
-    } else {
-        ProtocolUsageMode::Ignored
-    };
-    let urlparse = UrlParsing::new(
-        use_protocol,
-        pwm.get_current_setting_data().use_userinfo,
-        pwm.get_current_setting_data().use_subdomain,
-        pwm.get_current_setting_data().use_domain,
-        pwm.get_current_setting_data().use_params,
-    );
-    urlparse.parse(url.as_str()).into()
+    pwm.get_current_setting_data()
+        .used_text(url.as_str())
+        .into()
+}
+
+// soft hint only, generation still accepts any text
+fn on_validate_url(url: SharedString) -> bool {
+    is_probably_url(url.as_str()) && !on_url_edited(url).is_empty()
 }
 
-fn on_used_text_edited(url: SharedString, master: SharedString) -> SharedString {
+// Generation runs on a worker thread so long passwords don't block the UI. Every
+// request bumps the counter and a result is only shown while its number is current,
+// so a newer request supersedes one still in flight.
+fn on_used_text_edited(
+    app: slint::Weak<App>,
+    url: SharedString,
+    master: SharedString,
+) -> SharedString {
+    let number = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    // only hold the lock while copying the setting, not while hashing
+    let (setting, master, kdf) = match PWM_DATA.lock() {
+        Ok(pwm) => (
+            pwm.generation_setting(),
+            pwm.generation_master(master.to_string()),
+            pwm.master_kdf(),
+        ),
+        Err(_) => return SharedString::from("No Lock!"),
+    };
+    let setting = match setting {
+        Ok(setting) => setting,
+        Err(e) => return e.to_string().into(),
+    };
+    let request = GenerationRequest {
+        number,
+        app,
+        setting,
+        url: url.to_string(),
+        master,
+        kdf,
+    };
+    match GENERATION_WORKER.lock() {
+        Ok(worker) => {
+            if worker.send(request).is_err() {
+                return SharedString::from("No Generator!");
+            }
+        }
+        Err(_) => return SharedString::from("No Lock!"),
+    }
+    SharedString::from("…")
+}
+
+// the newest of first and whatever else is queued, the others are superseded anyway
+fn latest<T>(receiver: &Receiver<T>, first: T) -> T {
+    receiver.try_iter().last().unwrap_or(first)
+}
+
+fn generation_worker(receiver: Receiver<GenerationRequest>) {
+    while let Ok(first) = receiver.recv() {
+        generate_for(latest(&receiver, first));
+    }
+}
+
+fn generate_for(request: GenerationRequest) {
+    let number = request.number;
+    let current = || GENERATION.load(Ordering::SeqCst) == number;
+    if !current() {
+        return;
+    }
+    // the KDF is slow on purpose, so it runs here and not under the lock
+    let master = derive_master(&request.kdf, request.master);
+    if !current() {
+        return;
+    }
+    let text = match generate_password(&request.setting, request.url, master) {
+        Ok(pw) => {
+            if !current() {
+                return;
+            }
+            if let Ok(mut pwm) = PWM_DATA.lock() {
+                pwm.set_last_result(&pw);
+            }
+            pw
+        }
+        Err(e) => e.to_string(),
+    };
+    let _ = request.app.upgrade_in_event_loop(move |app| {
+        if GENERATION.load(Ordering::SeqCst) == number {
+            app.global::<PwSettings>().set_pw_created(text.into());
+        }
+    });
+}
+
+fn on_get_last_result() -> SharedString {
     match PWM_DATA.lock() {
-        Ok(mut pwm) => pwm
-            .create_password(url.to_string(), master.to_string())
-            .into(),
-        Err(_) => SharedString::from("No Lock!"),
+        Ok(pwm) => pwm.get_last_result().into(),
+        Err(_) => SharedString::from(""),
+    }
+}
+
+fn on_clear_last_result() {
+    if let Ok(mut pwm) = PWM_DATA.lock() {
+        pwm.clear_last_result();
     }
 }
 
@@ -121,6 +388,18 @@ fn on_get_current_setting() -> i32 {
     }
 }
 
+// one lock for both values, so the UI never sees an index past the end
+fn on_selection_state() -> SelectionState {
+    let (current, count) = match PWM_DATA.lock() {
+        Ok(pwm) => pwm.selection_state(),
+        Err(_) => (0, 0),
+    };
+    SelectionState {
+        current: current.try_into().unwrap_or(0),
+        count: count.try_into().unwrap_or(0),
+    }
+}
+
 fn on_set_current_setting(current_setting: i32) {
     match PWM_DATA.lock() {
         Ok(mut pwm) => match current_setting.try_into() {
@@ -172,6 +451,713 @@ fn on_set_setting_data(setting: PwmSlintSetting) {
     }
 }
 
+fn on_find_by_fingerprint(fp: SharedString) -> i32 {
+    match PWM_DATA.lock() {
+        Ok(pwm) => match pwm.find_by_fingerprint(fp.as_str()) {
+            Some(index) => index.try_into().unwrap_or(-1),
+            None => -1,
+        },
+        Err(_) => -1,
+    }
+}
+
+fn on_settings_empty() -> bool {
+    match PWM_DATA.lock() {
+        Ok(pwm) => pwm.settings_empty(),
+        Err(_) => false,
+    }
+}
+
+fn on_import_settings(path: SharedString, replace: bool) -> SharedString {
+    let mode = if replace {
+        ImportMode::Replace
+    } else {
+        ImportMode::Append
+    };
+    match PWM_DATA.lock() {
+        Ok(mut pwm) => match pwm.import_settings(path.as_str(), mode) {
+            Ok(_) => PwmConfigError::Ok.to_string().into(),
+            Err(e) => e.to_string().into(),
+        },
+        Err(_) => PwmConfigError::NoLock.to_string().into(),
+    }
+}
+
+// target is Plain or Compressed
+fn on_reencode_config(target: SharedString) -> SharedString {
+    let target = match ConfigFormat::from_str(target.as_str()) {
+        Ok(target) => target,
+        Err(_) => return PwmConfigError::Usage.to_string().into(),
+    };
+    match PWM_DATA.lock() {
+        Ok(mut pwm) => match pwm.reencode_config(target) {
+            Ok(_) => PwmConfigError::Ok.to_string().into(),
+            Err(e) => e.to_string().into(),
+        },
+        Err(_) => PwmConfigError::NoLock.to_string().into(),
+    }
+}
+
+fn on_write_diagnostics(path: SharedString) -> SharedString {
+    match PWM_DATA.lock() {
+        Ok(pwm) => match pwm.write_diagnostics(path.as_str()) {
+            Ok(_) => PwmConfigError::Ok.to_string().into(),
+            Err(e) => e.to_string().into(),
+        },
+        Err(_) => PwmConfigError::NoLock.to_string().into(),
+    }
+}
+
+fn on_get_share_url() -> SharedString {
+    match PWM_DATA.lock() {
+        Ok(pwm) => pwm.get_current_setting_data().to_share_url().into(),
+        Err(_) => SharedString::from(""),
+    }
+}
+
+fn on_setting_from_share_url(url: SharedString) -> PwmSlintSetting {
+    match PwmSetting::from_share_url(url.as_str()) {
+        Ok(setting) => setting.into(),
+        Err(_) => PWM_DEFAULT.clone().into(),
+    }
+}
+
+fn on_import_share_url(url: SharedString) -> SharedString {
+    match PWM_DATA.lock() {
+        Ok(mut pwm) => match pwm.import_share_url(url.as_str()) {
+            Ok(_) => PwmConfigError::Ok.to_string().into(),
+            Err(e) => e.to_string().into(),
+        },
+        Err(_) => PwmConfigError::NoLock.to_string().into(),
+    }
+}
+
+fn on_bulk_replace(field: SharedString, from: SharedString, to: SharedString) -> SharedString {
+    let field = match SettingField::from_str(field.as_str()) {
+        Ok(field) => field,
+        Err(e) => return e.to_string().into(),
+    };
+    let changed = match PWM_DATA.lock() {
+        Ok(mut pwm) => pwm.replace_in_field(field, from.as_str(), to.as_str()),
+        Err(_) => return SharedString::from("No Lock!"),
+    };
+    if changed > 0 && field.affects_generation() {
+        format!(
+            "{} settings changed, their generated passwords will change too",
+            changed
+        )
+        .into()
+    } else {
+        format!("{} settings changed", changed).into()
+    }
+}
+
+// field as named in the config, e.g. password_length; returns a summary to show
+fn on_bulk_apply(indices: ModelRc<i32>, field: SharedString, value: SharedString) -> SharedString {
+    let change = match FieldChange::parse(field.as_str(), value.as_str()) {
+        Some(change) => change,
+        None => return PwmConfigError::Usage.to_string().into(),
+    };
+    let indices = Vec::from_iter(indices.iter().filter_map(|i| usize::try_from(i).ok()));
+    let changed = match PWM_DATA.lock() {
+        Ok(mut pwm) => pwm.apply_to_indices(&indices, change),
+        Err(_) => return SharedString::from("No Lock!"),
+    };
+    format!("{} of {} settings changed", changed, indices.len()).into()
+}
+
+fn on_first_run() -> bool {
+    match PWM_DATA.lock() {
+        Ok(pwm) => pwm.is_first_run(),
+        Err(_) => false,
+    }
+}
+
+fn on_get_last_page() -> SharedString {
+    match PWM_DATA.lock() {
+        Ok(pwm) => pwm.get_last_page().into(),
+        Err(_) => PAGE_MAKE.into(),
+    }
+}
+
+fn on_set_last_page(page: SharedString) {
+    if let Ok(mut pwm) = PWM_DATA.lock() {
+        pwm.set_last_page(page.as_str());
+    }
+}
+
+fn on_get_settings_view_compact() -> bool {
+    match PWM_DATA.lock() {
+        Ok(pwm) => pwm.settings_view_compact(),
+        Err(_) => false,
+    }
+}
+
+fn on_set_settings_view_compact(compact: bool) {
+    if let Ok(mut pwm) = PWM_DATA.lock() {
+        pwm.set_settings_view_compact(compact);
+    }
+}
+
+fn on_set_length_preset(length: i32) -> i32 {
+    match PWM_DATA.lock() {
+        Ok(mut pwm) => pwm
+            .set_length_preset(length.try_into().unwrap_or_default())
+            .try_into()
+            .unwrap_or_default(),
+        Err(_) => length,
+    }
+}
+
+fn on_compatible_settings(forbidden: SharedString) -> ModelRc<SharedString> {
+    let names = match PWM_DATA.lock() {
+        Ok(pwm) => pwm.settings_compatible_with(forbidden.as_str()),
+        Err(_) => Vec::<String>::new(),
+    };
+    let vm_names = VecModel::from(Vec::from_iter(names.into_iter().map(SharedString::from)));
+    ModelRc::from(Rc::new(vm_names))
+}
+
+// scope is Names or All; field is the first of name, username, notes that matched
+fn on_search_settings_scoped(query: SharedString, scope: SharedString) -> ModelRc<SearchMatch> {
+    let matches = match (PWM_DATA.lock(), SearchScope::from_str(scope.as_str())) {
+        (Ok(pwm), Ok(scope)) => pwm.search_settings(query.as_str(), scope),
+        _ => Vec::new(),
+    };
+    let vm_matches = VecModel::from(Vec::from_iter(matches.into_iter().map(|(index, field)| {
+        SearchMatch {
+            index: index.try_into().unwrap_or(-1),
+            field: field.into(),
+        }
+    })));
+    ModelRc::from(Rc::new(vm_matches))
+}
+
+fn on_settings_by_algorithm(algo: SharedString) -> ModelRc<SharedString> {
+    let names = match PWM_DATA.lock() {
+        Ok(pwm) => pwm.settings_using_algorithm(algo.as_str()),
+        Err(_) => Vec::<String>::new(),
+    };
+    let vm_names = VecModel::from(Vec::from_iter(names.into_iter().map(SharedString::from)));
+    ModelRc::from(Rc::new(vm_names))
+}
+
+fn on_name_exists(name: SharedString) -> bool {
+    match PWM_DATA.lock() {
+        Ok(pwm) => pwm.name_exists(name.as_str()),
+        Err(_) => false,
+    }
+}
+
+fn on_get_raw_config() -> SharedString {
+    match PWM_DATA.lock() {
+        Ok(pwm) => match pwm.get_raw_config() {
+            Ok(toml) => toml.into(),
+            Err(_) => SharedString::from(""),
+        },
+        Err(_) => SharedString::from(""),
+    }
+}
+
+fn on_set_raw_config(text: SharedString) -> SharedString {
+    match PWM_DATA.lock() {
+        Ok(mut pwm) => match pwm.set_raw_config(text.as_str()) {
+            Ok(_) => PwmConfigError::Ok.to_string().into(),
+            Err(e) => e.to_string().into(),
+        },
+        Err(_) => PwmConfigError::NoLock.to_string().into(),
+    }
+}
+
+fn on_settings_equivalent(a: i32, b: i32) -> bool {
+    match (PWM_DATA.lock(), usize::try_from(a), usize::try_from(b)) {
+        (Ok(pwm), Ok(a), Ok(b)) => pwm.settings_equivalent(a, b),
+        _ => false,
+    }
+}
+
+fn on_char_histogram(password: SharedString) -> ModelRc<CharCount> {
+    let charset = match PWM_DATA.lock() {
+        Ok(pwm) => generation_characters(pwm.get_current_setting_data()).unwrap_or_default(),
+        Err(_) => String::new(),
+    };
+    let in_charset = character_histogram(password.as_str(), &charset)
+        .into_iter()
+        .map(|h| (h, true));
+    let foreign = foreign_characters(password.as_str(), &charset)
+        .into_iter()
+        .map(|h| (h, false));
+    let counts =
+        Vec::from_iter(
+            in_charset
+                .chain(foreign)
+                .map(|((c, count), in_charset)| CharCount {
+                    character: c.to_string().into(),
+                    count: count as i32,
+                    in_charset,
+                }),
+        );
+    ModelRc::from(Rc::new(VecModel::from(counts)))
+}
+
+fn on_clipboard_available() -> bool {
+    clipboard_available()
+}
+
+fn on_leet_ok(setting: PwmSlintSetting) -> SharedString {
+    match leet_config_ok(&PwmSetting::from(setting)) {
+        Ok(_) => SharedString::new(),
+        Err(e) => e.to_string().into(),
+    }
+}
+
+// used text with one url flag flipped, the stored setting is left alone
+fn on_url_preview_toggle(url: SharedString, flag: SharedString) -> SharedString {
+    let flag = match UrlFlag::from_str(flag.as_str()) {
+        Ok(flag) => flag,
+        Err(_) => return SharedString::new(),
+    };
+    let flags = match PWM_DATA.lock() {
+        Ok(pwm) => pwm.get_current_setting_data().url_flags(),
+        Err(_) => return SharedString::from("No Lock!"),
+    };
+    url_with_flags(url.as_str(), flags.toggled(flag)).into()
+}
+
+fn on_version_info() -> VersionInfo {
+    let (app, config) = match PWM_DATA.lock() {
+        Ok(pwm) => pwm.version_info(),
+        Err(_) => (String::from(env!("CARGO_PKG_VERSION")), 0),
+    };
+    VersionInfo {
+        app: app.into(),
+        config: config.try_into().unwrap_or(-1),
+    }
+}
+
+fn on_set_all_url_flags(value: bool) {
+    if let Ok(mut pwm) = PWM_DATA.lock() {
+        pwm.set_all_url_flags(value);
+    }
+}
+
+fn on_reset_url_flags() {
+    if let Ok(mut pwm) = PWM_DATA.lock() {
+        pwm.reset_url_flags();
+    }
+}
+
+fn on_crack_time() -> SharedString {
+    let bits = match PWM_DATA.lock() {
+        Ok(pwm) => {
+            let setting = pwm.get_current_setting_data();
+            if setting.passphrase_mode {
+                // five rolls of a six sided die per word
+                estimate_entropy_bits(setting.passphrase_words * ROLLS_PER_WORD, DICE_CHARACTERS)
+            } else {
+                let charset = generation_characters(setting).unwrap_or_default();
+                estimate_entropy_bits(setting.effective_length(), &charset)
+            }
+        }
+        Err(_) => return SharedString::from("No Lock!"),
+    };
+    crack_time_string(bits, DEFAULT_GUESSES_PER_SECOND).into()
+}
+
+fn on_min_charset_size(bits: f32, length: i32) -> SharedString {
+    min_charset_string(bits.into(), length.max(0) as usize).into()
+}
+
+fn on_master_quality(master: SharedString) -> SharedString {
+    master_quality(master.as_str()).to_string().into()
+}
+
+fn on_non_default_fields() -> ModelRc<SharedString> {
+    let fields = match PWM_DATA.lock() {
+        Ok(pwm) => pwm.get_current_setting_data().non_default_fields(),
+        Err(_) => Vec::<String>::new(),
+    };
+    let vm_fields = VecModel::from(Vec::from_iter(fields.into_iter().map(SharedString::from)));
+    ModelRc::from(Rc::new(vm_fields))
+}
+
+fn on_preview_import(path: SharedString) -> ImportPreview {
+    let result = match PWM_DATA.lock() {
+        Ok(mut pwm) => pwm.preview_import(path.as_str()),
+        Err(_) => Err(PwmConfigError::NoLock),
+    };
+    let (error, conflicts, valid, invalid) = match result {
+        Ok(preview) => (
+            PwmConfigError::Ok,
+            preview.conflicts,
+            preview.valid,
+            preview.invalid,
+        ),
+        Err(e) => (e, Vec::new(), 0, Vec::new()),
+    };
+    let conflicts = Vec::from_iter(conflicts.into_iter().map(|c| PwmSlintImportConflict {
+        name: c.name.into(),
+        existing_fingerprint: c.existing_fingerprint.into(),
+        incoming_fingerprint: c.incoming_fingerprint.into(),
+    }));
+    let invalid = Vec::from_iter(
+        invalid
+            .into_iter()
+            .map(|(name, e)| SharedString::from(format!("{}: {}", name, e))),
+    );
+    ImportPreview {
+        error: error.to_string().into(),
+        conflicts: ModelRc::from(Rc::new(VecModel::from(conflicts))),
+        valid: valid.try_into().unwrap_or(0),
+        invalid: ModelRc::from(Rc::new(VecModel::from(invalid))),
+    }
+}
+
+// one of Skip, Rename, Overwrite per conflict of the preview, unknown values skip
+fn on_apply_import(decisions: ModelRc<SharedString>) -> SharedString {
+    let decisions = Vec::from_iter(
+        decisions
+            .iter()
+            .map(|d| ConflictDecision::from_str(d.as_str()).unwrap_or(ConflictDecision::Skip)),
+    );
+    match PWM_DATA.lock() {
+        Ok(mut pwm) => match pwm.apply_import(&decisions) {
+            Ok(_) => PwmConfigError::Ok.to_string().into(),
+            Err(e) => e.to_string().into(),
+        },
+        Err(_) => PwmConfigError::NoLock.to_string().into(),
+    }
+}
+
+fn on_detect_collisions(url: SharedString, master: SharedString) -> ModelRc<SharedString> {
+    let collisions = match PWM_DATA.lock() {
+        Ok(pwm) => pwm.detect_collisions(url.as_str(), master.as_str()),
+        Err(_) => Vec::new(),
+    };
+    let vm_collisions = VecModel::from(Vec::from_iter(
+        collisions
+            .into_iter()
+            .map(|(a, b)| SharedString::from(format!("{} = {}", a, b))),
+    ));
+    ModelRc::from(Rc::new(vm_collisions))
+}
+
+fn on_extract_domain(url: SharedString) -> SharedString {
+    extract_domain(url.as_str()).into()
+}
+
+// The last generated password put into the setting's template, or alone when
+// the setting has none.
+fn on_format_output(url: SharedString) -> SharedString {
+    let (template, user, pass) = match PWM_DATA.lock() {
+        Ok(pwm) => {
+            let setting = pwm.get_current_setting_data();
+            (
+                setting.output_template.clone(),
+                setting.username.clone(),
+                pwm.get_last_result(),
+            )
+        }
+        Err(_) => return SharedString::from(""),
+    };
+    match template {
+        Some(template) => {
+            format_output(&template, &extract_domain(url.as_str()), &user, &pass).into()
+        }
+        None => pass.into(),
+    }
+}
+
+fn on_config_path() -> SharedString {
+    match PWM_DATA.lock() {
+        Ok(pwm) => match pwm.resolved_config_path() {
+            Ok(path) => path.into(),
+            Err(e) => e.to_string().into(),
+        },
+        Err(_) => PwmConfigError::NoLock.to_string().into(),
+    }
+}
+
+fn on_config_writable() -> bool {
+    match PWM_DATA.lock() {
+        Ok(pwm) => pwm.config_writable(),
+        Err(_) => false,
+    }
+}
+
+fn on_recovery_sheet(url: SharedString, master: SharedString) -> SharedString {
+    match PWM_DATA.lock() {
+        Ok(pwm) => pwm.recovery_sheet(url.as_str(), master.as_str()).into(),
+        Err(_) => PwmConfigError::NoLock.to_string().into(),
+    }
+}
+
+fn on_setting_warnings(index: i32) -> ModelRc<SharedString> {
+    let warnings = match (PWM_DATA.lock(), usize::try_from(index)) {
+        (Ok(pwm), Ok(index)) => pwm.setting_warnings(index),
+        _ => Vec::new(),
+    };
+    let vm_warnings = VecModel::from(Vec::from_iter(warnings.into_iter().map(SharedString::from)));
+    ModelRc::from(Rc::new(vm_warnings))
+}
+
+// url, test master; "Ok" or the error name
+fn on_set_canary(url: SharedString, test_master: SharedString) -> SharedString {
+    match PWM_DATA.lock() {
+        Ok(mut pwm) => match pwm.set_canary(url.as_str(), test_master.as_str()) {
+            Ok(_) => PwmConfigError::Ok.to_string().into(),
+            Err(e) => e.to_string().into(),
+        },
+        Err(_) => PwmConfigError::NoLock.to_string().into(),
+    }
+}
+
+// "None" without a canary, "Ok" or "Changed"
+fn on_check_canary(index: i32) -> SharedString {
+    let checked = match (PWM_DATA.lock(), usize::try_from(index)) {
+        (Ok(pwm), Ok(index)) => pwm.check_canary(index),
+        _ => None,
+    };
+    match checked {
+        None => "None".into(),
+        Some(true) => "Ok".into(),
+        Some(false) => "Changed".into(),
+    }
+}
+
+fn on_rotation_due(index: i32) -> bool {
+    match (PWM_DATA.lock(), usize::try_from(index)) {
+        (Ok(pwm), Ok(index)) => pwm.rotation_due(index),
+        _ => false,
+    }
+}
+
+// The PNG is decoded again for display, the Rust side only hands out bytes.
+fn on_setting_qr(index: i32) -> SettingQr {
+    let png = match (PWM_DATA.lock(), usize::try_from(index)) {
+        (Ok(pwm), Ok(index)) => pwm.share_url_qr(index),
+        (Ok(_), Err(_)) => Err(PwmConfigError::NoSuchSetting),
+        (Err(_), _) => Err(PwmConfigError::NoLock),
+    };
+    let failed = |e: PwmConfigError| SettingQr {
+        image: Image::default(),
+        error: e.to_string().into(),
+    };
+    let png = match png {
+        Ok(png) => png,
+        Err(e) => return failed(e),
+    };
+    let rgba = match image::load_from_memory(&png) {
+        Ok(decoded) => decoded.to_rgba8(),
+        Err(_) => return failed(PwmConfigError::FailQrCode),
+    };
+    let buffer = SharedPixelBuffer::<Rgba8Pixel>::clone_from_slice(
+        rgba.as_raw(),
+        rgba.width(),
+        rgba.height(),
+    );
+    SettingQr {
+        image: Image::from_rgba8(buffer),
+        error: PwmConfigError::Ok.to_string().into(),
+    }
+}
+
+fn on_unsupported_algorithms() -> ModelRc<SharedString> {
+    let names = unsupported_algorithms();
+    let vm_names = VecModel::from(Vec::from_iter(names.into_iter().map(SharedString::from)));
+    ModelRc::from(Rc::new(vm_names))
+}
+
+fn on_generate_with_algorithm(
+    url: SharedString,
+    master: SharedString,
+    algo: SharedString,
+) -> SharedString {
+    let result = match PWM_DATA.lock() {
+        Ok(pwm) => pwm.create_password_with_algorithm(url.as_str(), master.as_str(), algo.as_str()),
+        Err(_) => return PwmConfigError::NoLock.to_string().into(),
+    };
+    match result {
+        Ok(pw) => pw.into(),
+        Err(e) => e.to_string().into(),
+    }
+}
+
+fn on_generate_with_fingerprint(url: SharedString, master: SharedString) -> FingerprintedPassword {
+    let result = match PWM_DATA.lock() {
+        Ok(pwm) => pwm.generate_with_fingerprint(url.as_str(), master.as_str()),
+        Err(_) => {
+            return FingerprintedPassword {
+                error: PwmConfigError::NoLock.to_string().into(),
+                ..FingerprintedPassword::default()
+            }
+        }
+    };
+    match result {
+        Ok((pw, fingerprint)) => FingerprintedPassword {
+            error: PwmConfigError::Ok.to_string().into(),
+            password: pw.into(),
+            fingerprint: fingerprint.into(),
+        },
+        Err(e) => FingerprintedPassword {
+            error: e.to_string().into(),
+            ..FingerprintedPassword::default()
+        },
+    }
+}
+
+fn on_resolved_inputs(url: SharedString) -> PwmSlintResolvedInputs {
+    let resolved = match PWM_DATA.lock() {
+        Ok(pwm) => pwm.resolved_inputs(url.as_str()),
+        Err(_) => {
+            return PwmSlintResolvedInputs {
+                error: PwmConfigError::NoLock.to_string().into(),
+                ..Default::default()
+            }
+        }
+    };
+    match resolved {
+        Ok(inputs) => PwmSlintResolvedInputs {
+            error: SharedString::new(),
+            hash_algorithm: inputs.hash_algorithm.into(),
+            characters: inputs.characters.into(),
+            length: inputs.length.try_into().unwrap_or(0),
+            username: inputs.username.into(),
+            prefix: inputs.prefix.into(),
+            suffix: inputs.suffix.into(),
+            used_text: inputs.used_text.into(),
+        },
+        Err(e) => PwmSlintResolvedInputs {
+            error: e.to_string().into(),
+            ..Default::default()
+        },
+    }
+}
+
+fn on_snapshot() {
+    let snap = match PWM_DATA.lock() {
+        Ok(pwm) => pwm.snapshot(),
+        Err(_) => return,
+    };
+    if let Ok(mut slot) = SNAPSHOT.lock() {
+        *slot = Some(snap);
+    }
+}
+
+// the snapshot stays in its slot, it can be restored again
+fn on_restore() -> bool {
+    let snap = match SNAPSHOT.lock() {
+        Ok(slot) => match slot.as_ref() {
+            Some(snap) => snap.clone(),
+            None => return false,
+        },
+        Err(_) => return false,
+    };
+    match PWM_DATA.lock() {
+        Ok(mut pwm) => {
+            pwm.restore(snap);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+fn on_undo() -> bool {
+    match PWM_DATA.lock() {
+        Ok(mut pwm) => pwm.undo(),
+        Err(_) => false,
+    }
+}
+
+fn on_redo() -> bool {
+    match PWM_DATA.lock() {
+        Ok(mut pwm) => pwm.redo(),
+        Err(_) => false,
+    }
+}
+
+fn on_leet_stages(url: SharedString, master: SharedString) -> LeetStages {
+    let (before, after) = match PWM_DATA.lock() {
+        Ok(pwm) => pwm.leet_stages(url.as_str(), master.as_str()),
+        Err(_) => (String::new(), String::new()),
+    };
+    LeetStages {
+        before: before.into(),
+        after: after.into(),
+    }
+}
+
+fn on_generate_all_leet(url: SharedString, master: SharedString) -> ModelRc<SharedString> {
+    let levels = match PWM_DATA.lock() {
+        Ok(pwm) => pwm.generate_all_leet_levels(url.as_str(), master.as_str()),
+        Err(_) => Vec::new(),
+    };
+    let vm_levels = VecModel::from(Vec::from_iter(
+        levels
+            .into_iter()
+            .map(|(level, pw)| SharedString::from(format!("{}: {}", level, pw))),
+    ));
+    ModelRc::from(Rc::new(vm_levels))
+}
+
+// masters one per line, blank lines skipped
+fn on_distinctness(url: SharedString, masters: SharedString) -> Distinctness {
+    let masters = Vec::from_iter(
+        masters
+            .lines()
+            .filter(|master| !master.is_empty())
+            .map(String::from),
+    );
+    let (distinct, total) = match PWM_DATA.lock() {
+        Ok(pwm) => pwm.distinctness(url.as_str(), &masters),
+        Err(_) => (0, masters.len()),
+    };
+    Distinctness {
+        distinct: distinct.try_into().unwrap_or(i32::MAX),
+        total: total.try_into().unwrap_or(i32::MAX),
+    }
+}
+
+// Copies the share link, which never contains the modifier or the notes.
+// Nothing secret ends up in the clipboard this way.
+fn on_copy_setting_share() -> SharedString {
+    let url = match PWM_DATA.lock() {
+        Ok(pwm) => pwm.get_current_setting_data().to_share_url(),
+        Err(_) => return PwmConfigError::NoLock.to_string().into(),
+    };
+    match copy_to_clipboard(&url) {
+        Ok(_) => PwmConfigError::Ok.to_string().into(),
+        Err(e) => e.to_string().into(),
+    }
+}
+
+fn on_charset_display(charset: SharedString, max: i32) -> SharedString {
+    charset_display(charset.as_str(), max.max(0) as usize).into()
+}
+
+// the risky characters of the charset, empty when there are none
+fn on_risky_chars(charset: SharedString) -> SharedString {
+    String::from_iter(risky_charset_chars(charset.as_str())).into()
+}
+
+fn on_charset_bias(charset: SharedString, algo: SharedString) -> SharedString {
+    charset_bias_text(charset.as_str(), algo.as_str()).into()
+}
+
+fn get_length_presets() -> ModelRc<i32> {
+    let presets = Vec::from_iter(LENGTH_PRESETS.iter().map(|p| *p as i32));
+    ModelRc::from(Rc::new(VecModel::from(presets)))
+}
+
+// recently picked algorithms first, refreshed when the settings page is closed
+fn on_hash_algorithms_mru() -> ModelRc<SharedString> {
+    let hash_algorithms = match PWM_DATA.lock() {
+        Ok(pwm) => pwm.get_hash_algorithms_mru(),
+        Err(_) => Vec::from(HashAlgorithm::VARIANTS),
+    };
+    get_vecmodel_from_enum(&hash_algorithms)
+}
+
 fn get_vecmodel_from_enum(enum_variant_names: &[&str]) -> ModelRc<SharedString> {
     let enum_names = Vec::from_iter(
         enum_variant_names
@@ -183,23 +1169,98 @@ fn get_vecmodel_from_enum(enum_variant_names: &[&str]) -> ModelRc<SharedString>
 }
 
 fn main() -> Result<(), PwmConfigError> {
-    let _error = match PWM_DATA.lock() {
-        Ok(mut pwm) => match pwm.load_settings() {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
-        },
+    debug_assert!(
+        enum_names_parse(),
+        "a dropdown entry does not parse back with FromStr"
+    );
+    let args = Vec::from_iter(std::env::args().skip(1));
+    match args.first().map(String::as_str) {
+        Some("batch") => return pwm_cli::run_batch(&args[1..]),
+        Some("env") => return pwm_cli::run_env(&args[1..]),
+        Some("init") => return pwm_cli::run_init(&args[1..]),
+        _ => (),
+    }
+    let demo = args.iter().any(|arg| arg == "--demo");
+    let read_only = args.iter().any(|arg| arg == "--read-only")
+        || std::env::var("PASSWORDMAKER_READONLY").is_ok_and(|v| !v.is_empty() && v != "0");
+    let compress = args.iter().any(|arg| arg == "--compress")
+        || std::env::var("PASSWORDMAKER_COMPRESS").is_ok_and(|v| !v.is_empty() && v != "0");
+    let keyring = args.iter().any(|arg| arg == "--keyring")
+        || std::env::var("PASSWORDMAKER_KEYRING").is_ok_and(|v| !v.is_empty() && v != "0");
+    let portable = args.iter().any(|arg| arg == "--portable");
+    if portable {
+        match PWM_DATA.lock() {
+            Ok(mut pwm) => pwm.enable_portable(),
+            Err(_) => return Err(PwmConfigError::NoLock),
+        }
+    }
+    // before loading, the settings are read from the keyring
+    if keyring {
+        match PWM_DATA.lock() {
+            Ok(mut pwm) => pwm.enable_keyring(),
+            Err(_) => return Err(PwmConfigError::NoLock),
+        }
+    }
+    let load_errors = match PWM_DATA.lock() {
+        Ok(mut pwm) => {
+            let mut errors = match pwm.load_and_validate() {
+                Ok(_) => Vec::new(),
+                Err(errors) => errors,
+            };
+            // e.g. a stick mounted read-only, say so now rather than at the first save
+            if portable && !pwm.config_writable() {
+                errors.push(PwmSettingsError::ConfigError {
+                    error: PwmConfigError::FailOpenForWrite,
+                });
+            }
+            errors
+        }
         Err(_) => return Err(PwmConfigError::NoLock),
     };
+    if demo {
+        match PWM_DATA.lock() {
+            Ok(mut pwm) => pwm.enable_demo_mode(),
+            Err(_) => return Err(PwmConfigError::NoLock),
+        }
+    }
+    if read_only {
+        match PWM_DATA.lock() {
+            Ok(mut pwm) => pwm.enable_read_only(),
+            Err(_) => return Err(PwmConfigError::NoLock),
+        }
+    }
+    if compress {
+        match PWM_DATA.lock() {
+            Ok(mut pwm) => pwm.enable_compression(),
+            Err(_) => return Err(PwmConfigError::NoLock),
+        }
+    }
     let app = match App::new() {
         Ok(app) => app,
+        // no display, but someone to ask for the inputs
+        Err(_) if std::io::stdin().is_terminal() => {
+            eprintln!("GUI unavailable, generating on the terminal");
+            return pwm_cli::run_repl(demo, read_only);
+        }
         Err(_) => return Err(PwmConfigError::NoApp),
     };
     app.global::<UiSettings>()
-        .set_hash_algorithms(get_vecmodel_from_enum(HashAlgorithm::VARIANTS));
+        .set_hash_algorithms(on_hash_algorithms_mru());
     app.global::<UiSettings>()
         .set_use_leet(get_vecmodel_from_enum(UseLeetWhenGenerating::VARIANTS));
     app.global::<UiSettings>()
         .set_leet_level(get_vecmodel_from_enum(LeetLevel::VARIANTS));
+    app.global::<UiSettings>()
+        .set_load_errors(ModelRc::from(Rc::new(VecModel::from(Vec::from_iter(
+            load_errors
+                .iter()
+                .map(|e| SharedString::from(e.to_string())),
+        )))));
+    app.global::<UiSettings>().set_read_only(read_only);
+    app.global::<UiSettings>()
+        .set_length_presets(get_length_presets());
+    app.global::<UiSettings>()
+        .set_max_password_length(MAX_PASSWORD_LENGTH as i32);
     app.global::<UiSettings>()
         .set_current_setting(on_get_current_setting());
     app.global::<UiSettings>()
@@ -208,10 +1269,18 @@ fn main() -> Result<(), PwmConfigError> {
         .set_available_settings(on_get_available_settings());
     app.global::<MakePageCallback>()
         .on_url_edited(|url| on_url_edited(url));
-    app.global::<MakePageCallback>()
-        .on_used_text_edited(|url, master| on_used_text_edited(url, master));
+    app.global::<MakePageCallback>().on_used_text_edited({
+        let app = app.as_weak();
+        move |url, master| on_used_text_edited(app.clone(), url, master)
+    });
     app.global::<MakePageCallback>()
         .on_pw_edited(|master| on_pw_edited(master));
+    app.global::<MakePageCallback>()
+        .on_validate_url(on_validate_url);
+    app.global::<MakePageCallback>()
+        .on_get_last_result(on_get_last_result);
+    app.global::<MakePageCallback>()
+        .on_clear_last_result(on_clear_last_result);
     app.global::<SettingsPageCallback>()
         .on_get_current_setting(|| on_get_current_setting());
     app.global::<SettingsPageCallback>()
@@ -226,6 +1295,130 @@ fn main() -> Result<(), PwmConfigError> {
         .on_get_setting_data(|| on_get_setting_data());
     app.global::<SettingsPageCallback>()
         .on_set_setting_data(|setting| on_set_setting_data(setting));
+    app.global::<SettingsPageCallback>()
+        .on_import_settings(on_import_settings);
+    app.global::<SettingsPageCallback>()
+        .on_find_by_fingerprint(on_find_by_fingerprint);
+    app.global::<SettingsPageCallback>()
+        .on_settings_empty(on_settings_empty);
+    app.global::<SettingsPageCallback>()
+        .on_get_share_url(on_get_share_url);
+    app.global::<SettingsPageCallback>()
+        .on_setting_from_share_url(on_setting_from_share_url);
+    app.global::<SettingsPageCallback>()
+        .on_bulk_replace(on_bulk_replace);
+    app.global::<SettingsPageCallback>()
+        .on_get_last_page(on_get_last_page);
+    app.global::<SettingsPageCallback>()
+        .on_first_run(on_first_run);
+    app.global::<SettingsPageCallback>()
+        .on_reencode_config(on_reencode_config);
+    app.global::<SettingsPageCallback>()
+        .on_bulk_apply(on_bulk_apply);
+    app.global::<SettingsPageCallback>()
+        .on_write_diagnostics(on_write_diagnostics);
+    app.global::<SettingsPageCallback>()
+        .on_set_last_page(on_set_last_page);
+    app.global::<SettingsPageCallback>()
+        .on_get_settings_view_compact(on_get_settings_view_compact);
+    app.global::<SettingsPageCallback>()
+        .on_set_settings_view_compact(on_set_settings_view_compact);
+    app.global::<SettingsPageCallback>()
+        .on_set_length_preset(on_set_length_preset);
+    app.global::<SettingsPageCallback>()
+        .on_hash_algorithms_mru(on_hash_algorithms_mru);
+    app.global::<SettingsPageCallback>()
+        .on_compatible_settings(on_compatible_settings);
+    app.global::<SettingsPageCallback>()
+        .on_settings_by_algorithm(on_settings_by_algorithm);
+    app.global::<SettingsPageCallback>()
+        .on_name_exists(on_name_exists);
+    app.global::<SettingsPageCallback>()
+        .on_search_settings_scoped(on_search_settings_scoped);
+    app.global::<SettingsPageCallback>()
+        .on_get_raw_config(on_get_raw_config);
+    app.global::<SettingsPageCallback>()
+        .on_set_raw_config(on_set_raw_config);
+    app.global::<SettingsPageCallback>()
+        .on_settings_equivalent(on_settings_equivalent);
+    app.global::<MakePageCallback>()
+        .on_char_histogram(on_char_histogram);
+    app.global::<MakePageCallback>()
+        .on_clipboard_available(on_clipboard_available);
+    app.global::<SettingsPageCallback>().on_leet_ok(on_leet_ok);
+    app.global::<MakePageCallback>()
+        .on_url_preview_toggle(on_url_preview_toggle);
+    app.global::<SettingsPageCallback>()
+        .on_version_info(on_version_info);
+    app.global::<SettingsPageCallback>()
+        .on_set_all_url_flags(on_set_all_url_flags);
+    app.global::<MakePageCallback>()
+        .on_crack_time(on_crack_time);
+    app.global::<MakePageCallback>()
+        .on_min_charset_size(on_min_charset_size);
+    app.global::<MakePageCallback>()
+        .on_master_quality(on_master_quality);
+    app.global::<SettingsPageCallback>()
+        .on_non_default_fields(on_non_default_fields);
+    app.global::<SettingsPageCallback>()
+        .on_preview_import(on_preview_import);
+    app.global::<SettingsPageCallback>()
+        .on_apply_import(on_apply_import);
+    app.global::<SettingsPageCallback>()
+        .on_reset_url_flags(on_reset_url_flags);
+    app.global::<MakePageCallback>()
+        .on_detect_collisions(on_detect_collisions);
+    app.global::<MakePageCallback>()
+        .on_extract_domain(on_extract_domain);
+    app.global::<MakePageCallback>()
+        .on_leet_stages(on_leet_stages);
+    app.global::<MakePageCallback>()
+        .on_generate_all_leet(on_generate_all_leet);
+    app.global::<MakePageCallback>()
+        .on_distinctness(on_distinctness);
+    app.global::<MakePageCallback>()
+        .on_format_output(on_format_output);
+    app.global::<MakePageCallback>()
+        .on_resolved_inputs(on_resolved_inputs);
+    app.global::<MakePageCallback>()
+        .on_generate_with_algorithm(on_generate_with_algorithm);
+    app.global::<MakePageCallback>()
+        .on_generate_with_fingerprint(on_generate_with_fingerprint);
+    app.global::<SettingsPageCallback>()
+        .on_selection_state(on_selection_state);
+    app.global::<SettingsPageCallback>()
+        .on_config_writable(on_config_writable);
+    app.global::<SettingsPageCallback>()
+        .on_config_path(on_config_path);
+    app.global::<SettingsPageCallback>()
+        .on_recovery_sheet(on_recovery_sheet);
+    app.global::<SettingsPageCallback>()
+        .on_unsupported_algorithms(on_unsupported_algorithms);
+    app.global::<SettingsPageCallback>()
+        .on_setting_warnings(on_setting_warnings);
+    app.global::<SettingsPageCallback>()
+        .on_setting_qr(on_setting_qr);
+    app.global::<SettingsPageCallback>()
+        .on_rotation_due(on_rotation_due);
+    app.global::<SettingsPageCallback>()
+        .on_set_canary(on_set_canary);
+    app.global::<SettingsPageCallback>()
+        .on_check_canary(on_check_canary);
+    app.global::<SettingsPageCallback>()
+        .on_snapshot(on_snapshot);
+    app.global::<SettingsPageCallback>().on_restore(on_restore);
+    app.global::<SettingsPageCallback>().on_undo(on_undo);
+    app.global::<SettingsPageCallback>().on_redo(on_redo);
+    app.global::<SettingsPageCallback>()
+        .on_copy_setting_share(on_copy_setting_share);
+    app.global::<SettingsPageCallback>()
+        .on_charset_display(on_charset_display);
+    app.global::<SettingsPageCallback>()
+        .on_charset_bias(on_charset_bias);
+    app.global::<SettingsPageCallback>()
+        .on_risky_chars(on_risky_chars);
+    app.global::<SettingsPageCallback>()
+        .on_import_share_url(on_import_share_url);
     match app.run() {
         Ok(_) => (),
         Err(_) => return Err(PwmConfigError::NoApp),
@@ -235,3 +1428,140 @@ fn main() -> Result<(), PwmConfigError> {
         Err(_) => Err(PwmConfigError::NoLock),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latest_skips_to_the_last_queued_request() {
+        let (sender, receiver) = mpsc::channel();
+        assert_eq!(latest(&receiver, 1), 1);
+        for request in 2..=4 {
+            sender.send(request).unwrap();
+        }
+        assert_eq!(latest(&receiver, 1), 4);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    fn round_trip(setting: PwmSetting) -> PwmSetting {
+        PwmSlintSetting::from(setting).into()
+    }
+
+    #[test]
+    fn populated_setting_round_trips() {
+        let setting = PwmSetting {
+            name: String::from("name"),
+            hash_algorithm: String::from("Sha256"),
+            use_leet: String::from("Before"),
+            leet_level: String::from("Level3"),
+            characters: String::from("abc123"),
+            username: String::from("user"),
+            modifier: String::from("mod"),
+            password_length: 17,
+            prefix: String::from("pre"),
+            suffix: String::from("suf"),
+            use_domain: true,
+            use_subdomain: false,
+            use_protocol: true,
+            use_params: false,
+            use_userinfo: true,
+            excluded_characters: String::from("1"),
+            notes: String::from("notes"),
+            modifier_date_format: Some(String::from("%Y")),
+            created: None,
+            canary: None,
+            max_output_length: Some(13),
+            passphrase_mode: true,
+            passphrase_words: 7,
+            passphrase_separator: String::from("-"),
+            separator: String::from("."),
+            output_template: Some(String::from("{user}:{pass}")),
+            charset_file: Some(String::from("chars.txt")),
+            use_username: !PWM_DEFAULT.use_username,
+            force_case: String::from("upper"),
+            turkish_case: !PWM_DEFAULT.turkish_case,
+            trim_url: !PWM_DEFAULT.trim_url,
+            rotate_days: Some(90),
+            url_generation_overrides: UrlFlags::from_digits("10110"),
+        };
+        let back = round_trip(setting.clone());
+        assert_eq!(back.name, setting.name);
+        assert_eq!(back.hash_algorithm, setting.hash_algorithm);
+        assert_eq!(back.use_leet, setting.use_leet);
+        assert_eq!(back.leet_level, setting.leet_level);
+        assert_eq!(back.characters, setting.characters);
+        assert_eq!(back.username, setting.username);
+        assert_eq!(back.modifier, setting.modifier);
+        assert_eq!(back.password_length, setting.password_length);
+        assert_eq!(back.prefix, setting.prefix);
+        assert_eq!(back.suffix, setting.suffix);
+        assert_eq!(back.excluded_characters, setting.excluded_characters);
+        assert_eq!(back.notes, setting.notes);
+        assert_eq!(back.modifier_date_format, setting.modifier_date_format);
+        assert_eq!(back.max_output_length, setting.max_output_length);
+        assert_eq!(back.passphrase_mode, setting.passphrase_mode);
+        assert_eq!(back.passphrase_words, setting.passphrase_words);
+        assert_eq!(back.passphrase_separator, setting.passphrase_separator);
+        assert_eq!(back.separator, setting.separator);
+        assert_eq!(back.output_template, setting.output_template);
+        assert_eq!(back.charset_file, setting.charset_file);
+        assert_eq!(back.use_username, setting.use_username);
+        assert_eq!(back.force_case, setting.force_case);
+        assert_eq!(back.turkish_case, setting.turkish_case);
+        assert_eq!(back.trim_url, setting.trim_url);
+        assert_eq!(back.rotate_days, setting.rotate_days);
+        assert_eq!(
+            back.url_generation_overrides,
+            setting.url_generation_overrides
+        );
+        assert!(back == setting);
+    }
+
+    // domain, subdomain, protocol, params, userinfo
+    fn url_flags_of(setting: &PwmSetting) -> [bool; 5] {
+        [
+            setting.use_domain,
+            setting.use_subdomain,
+            setting.use_protocol,
+            setting.use_params,
+            setting.use_userinfo,
+        ]
+    }
+
+    #[test]
+    fn each_url_flag_round_trips_on_its_own() {
+        for on in 0..5 {
+            let flags: [bool; 5] = std::array::from_fn(|i| i == on);
+            let mut setting = PWM_DEFAULT.clone();
+            [
+                setting.use_domain,
+                setting.use_subdomain,
+                setting.use_protocol,
+                setting.use_params,
+                setting.use_userinfo,
+            ] = flags;
+            assert_eq!(url_flags_of(&round_trip(setting)), flags);
+        }
+    }
+
+    #[test]
+    fn unusable_passphrase_words_fall_back_to_the_default() {
+        for words in [0, -3] {
+            let mut slint_setting = PwmSlintSetting::from(PWM_DEFAULT.clone());
+            slint_setting.passphrase_words = words;
+            let setting = PwmSetting::from(slint_setting);
+            assert_eq!(setting.passphrase_words, PWM_DEFAULT.passphrase_words);
+        }
+    }
+
+    #[test]
+    fn unusable_password_length_falls_back_to_the_default() {
+        for length in [0, -1, i32::MIN] {
+            let mut slint_setting = PwmSlintSetting::from(PWM_DEFAULT.clone());
+            slint_setting.password_length = length;
+            let setting = PwmSetting::from(slint_setting);
+            assert_eq!(setting.password_length, PWM_DEFAULT.password_length);
+        }
+    }
+}
@@ -0,0 +1,211 @@
+// Torsten Sadowski
+// SPDX-License-Identifier:  GPL-3.0-or-later
+
+use crate::pwm_gui_data::{derive_master, generate_password, PwmConfigError, PwmGui, PwmGuiData};
+use crate::pwm_settings::PwmSetting;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, Write},
+    path::Path,
+};
+use zeroize::Zeroize;
+
+// the loaded data is returned too for config wide options like the master KDF
+fn load_setting(name: &str) -> Result<(PwmGuiData, PwmSetting), PwmConfigError> {
+    let mut pwm = PwmGuiData::new();
+    if std::env::var("PASSWORDMAKER_KEYRING").is_ok_and(|v| !v.is_empty() && v != "0") {
+        pwm.enable_keyring();
+    }
+    pwm.load_settings()?;
+    match pwm.setting_by_name(name) {
+        Some(setting) => Ok((pwm, setting)),
+        None => Err(PwmConfigError::NoSuchSetting),
+    }
+}
+
+// The file holds passwords, so on Unix only the owner may read it.
+fn create_secret_file(path: &str) -> Result<File, PwmConfigError> {
+    let parent = match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if !parent.is_dir() {
+        eprintln!("output directory {} does not exist", parent.display());
+        return Err(PwmConfigError::NoOutputDir);
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    match options.open(path) {
+        Ok(file) => Ok(file),
+        Err(_) => Err(PwmConfigError::FailOpenForWrite),
+    }
+}
+
+// batch --setting <name> --urls <file> [--output <file>]
+// Reads the master password from stdin and prints "url<TAB>password" for every
+// non-blank line of the file, to the output file instead of stdout if given.
+// A url that fails to generate gets an error marker instead of a password, the
+// rest of the batch still runs.
+pub fn run_batch(args: &[String]) -> Result<(), PwmConfigError> {
+    let mut setting_name = None;
+    let mut urls_path = None;
+    let mut output_path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--setting" => setting_name = args.next(),
+            "--urls" => urls_path = args.next(),
+            "--output" => output_path = args.next(),
+            _ => return Err(PwmConfigError::Usage),
+        }
+    }
+    let (setting_name, urls_path) = match (setting_name, urls_path) {
+        (Some(setting_name), Some(urls_path)) => (setting_name, urls_path),
+        _ => {
+            eprintln!("usage: batch --setting <name> --urls <file> [--output <file>]");
+            return Err(PwmConfigError::Usage);
+        }
+    };
+
+    let (pwm, setting) = load_setting(setting_name)?;
+    let urls = match fs::read_to_string(urls_path) {
+        Ok(urls) => urls,
+        Err(_) => return Err(PwmConfigError::FailRead),
+    };
+    let mut master = String::new();
+    if io::stdin().read_line(&mut master).is_err() {
+        return Err(PwmConfigError::FailRead);
+    }
+    let master_len = master.trim_end_matches(['\r', '\n']).len();
+    master.truncate(master_len);
+    let mut output: Box<dyn Write> = match output_path {
+        Some(path) => Box::new(create_secret_file(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut master = derive_master(&pwm.master_kdf(), master);
+
+    let mut written = Ok(());
+    for url in urls.lines().map(str::trim).filter(|url| !url.is_empty()) {
+        let line = match generate_password(&setting, setting.used_text(url), master.clone()) {
+            Ok(pw) => writeln!(output, "{}\t{}", url, pw),
+            Err(e) => writeln!(output, "{}\t!error: {}", url, e),
+        };
+        if line.is_err() {
+            written = Err(PwmConfigError::FailWrite);
+            break;
+        }
+    }
+    master.zeroize();
+    written
+}
+
+// env --setting <name> [--with-secrets]
+// Prints the setting as shell assignments, e.g. for eval "$(passwordmaker_slint env ...)".
+pub fn run_env(args: &[String]) -> Result<(), PwmConfigError> {
+    let mut setting_name = None;
+    let mut with_secrets = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--setting" => setting_name = args.next(),
+            "--with-secrets" => with_secrets = true,
+            _ => return Err(PwmConfigError::Usage),
+        }
+    }
+    let setting_name = match setting_name {
+        Some(setting_name) => setting_name,
+        None => {
+            eprintln!("usage: env --setting <name> [--with-secrets]");
+            return Err(PwmConfigError::Usage);
+        }
+    };
+
+    let (_, setting) = load_setting(setting_name)?;
+    print!("{}", setting.to_env(with_secrets));
+    Ok(())
+}
+
+// init [--force]
+// Writes a config with one default setting to where the GUI would save it and
+// prints the path. An existing config is only replaced with --force.
+pub fn run_init(args: &[String]) -> Result<(), PwmConfigError> {
+    let mut force = false;
+    for arg in args {
+        match arg.as_str() {
+            "--force" => force = true,
+            _ => {
+                eprintln!("usage: init [--force]");
+                return Err(PwmConfigError::Usage);
+            }
+        }
+    }
+
+    let mut pwm = PwmGuiData::new();
+    if std::env::var("PASSWORDMAKER_COMPRESS").is_ok_and(|v| !v.is_empty() && v != "0") {
+        pwm.enable_compression();
+    }
+    if let Err(e) = pwm.init_settings(force) {
+        if let PwmConfigError::ConfigExists = e {
+            eprintln!("config exists, use --force to replace it");
+        }
+        return Err(e);
+    }
+    println!("{}", pwm.resolved_config_path()?);
+    Ok(())
+}
+
+fn prompt(text: &str) -> Option<String> {
+    eprint!("{}", text);
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end_matches(['\r', '\n']).to_string()),
+    }
+}
+
+// Used when the GUI can't start but there is a terminal, e.g. over SSH.
+// Asks for setting, url and master until the input ends; the master is read
+// without echo. --demo and --read-only apply as in the GUI, in demo mode
+// there is no master to ask for.
+pub fn run_repl(demo: bool, read_only: bool) -> Result<(), PwmConfigError> {
+    while let Some(setting_name) = prompt("setting: ") {
+        let (mut pwm, setting) = match load_setting(&setting_name) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+        if demo {
+            pwm.enable_demo_mode();
+        }
+        if read_only {
+            pwm.enable_read_only();
+        }
+        let url = match prompt("url: ") {
+            Some(url) => url,
+            None => break,
+        };
+        let master = if demo {
+            String::new()
+        } else {
+            match rpassword::prompt_password("master: ") {
+                Ok(master) => master,
+                Err(_) => return Err(PwmConfigError::FailRead),
+            }
+        };
+        let master = pwm.generation_master(master);
+        let mut master = derive_master(&pwm.master_kdf(), master);
+        match generate_password(&setting, setting.used_text(&url), master.clone()) {
+            Ok(pw) => println!("{}", pw),
+            Err(e) => eprintln!("!error: {}", e),
+        }
+        master.zeroize();
+    }
+    Ok(())
+}
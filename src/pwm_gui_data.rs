@@ -1,27 +1,45 @@
 // Torsten Sadowski
 // SPDX-License-Identifier:  GPL-3.0-or-later
 
+use crate::pwm_passphrase::{dice_to_passphrase, DICE_CHARACTERS, ROLLS_PER_WORD};
 use crate::pwm_settings::{
-    LeetError, PwmSetting, PwmSettings, PwmSettingsAccess, PwmSettingsError,
+    generation_equivalent, url_with_flags, Canary, ConflictDecision, FieldChange, ForceCase,
+    ImportConflict, KdfConfig, LeetError, PwmConfig, PwmSetting, PwmSettings, PwmSettingsAccess,
+    PwmSettingsError, SearchScope, SettingField, UrlFlags,
 };
+use chrono::{Local, NaiveDateTime};
 use digest::Digest;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use image::{DynamicImage, ImageFormat, Luma};
 use md4;
 use md5;
+use once_cell::sync::Lazy;
 use passwordmaker_rs::{
     HashAlgorithm, Hasher, HasherList, LeetLevel, PasswordMaker, UseLeetWhenGenerating,
     UseLeetWhenGeneratingDiscriminants,
 };
+use qrcode::{types::QrError, QrCode};
 use ripemd;
 use sha1;
 use sha2;
 use slint::SharedString;
 use std::{
+    collections::HashSet,
     env::{var, VarError},
-    fs::{self, File},
-    io::Write,
+    fmt::Write as _,
+    fs::{self, File, OpenOptions},
+    io::{Cursor, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
     str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
 };
-use strum_macros::Display;
+use strum::VariantNames;
+use strum_macros::{Display, EnumString};
+use zeroize::Zeroize;
 
 pub struct Md4;
 pub struct Md5;
@@ -85,16 +103,552 @@ pub enum PwmConfigError {
     FailOpenForRead,
     FailRead,
     Str2Toml,
+    Toml2Str,
+    ConfigTooNew,
+    InvalidShareUrl,
+    NotAShareUrl,
+    ShareUrlEncoding,
+    Usage,
+    NoSuchSetting,
+    FailCreateDir,
+    NoPendingImport,
+    NoClipboard,
+    FailClipboard,
+    ConcurrentModification,
+    Decompress,
+    Compress,
+    ConfigExists,
+    NoOutputDir,
+    ShareUrlTooLong,
+    FailQrCode,
+    KeyringUnavailable,
+    // the keyring has no file format to change
+    ConfigInKeyring,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub enum ImportMode {
+    // add the imported settings, renaming duplicates
+    #[default]
+    Append,
+    // back up the current settings, then use the imported ones
+    Replace,
+}
+
+// How the config file is stored. There is no encrypted format yet.
+#[derive(Debug, Clone, Copy, PartialEq, EnumString)]
+pub enum ConfigFormat {
+    Plain,
+    Compressed,
 }
 
 pub struct PwmGuiData {
-    settings: PwmSettings,
+    config: PwmConfig,
     settings_error: PwmSettingsError,
     error: PwmConfigError,
+    inputs: Option<PwmInputs>,
+    // last generated password, memory only
+    last_result: String,
+    // set when the file on disk was written by a newer version and must not be overwritten
+    keep_config_file: bool,
+    // --demo: fixed master, nothing saved
+    demo: bool,
+    // settings changed since load/save
+    dirty: bool,
+    // --read-only / PASSWORDMAKER_READONLY: config is managed elsewhere, never write it
+    read_only: bool,
+    // settings read by preview_import, waiting for the user's decisions
+    pending_import: Option<PwmSettings>,
+    // modification time of the config file when it was read or last written
+    config_mtime: Option<SystemTime>,
+    // settings before recent edits, oldest first; memory only, never saved
+    undo_history: Vec<PwmSettings>,
+    redo_history: Vec<PwmSettings>,
+    // --compress / PASSWORDMAKER_COMPRESS: save gzipped to passwordmaker.toml.gz
+    compress: bool,
+    // there was no config file at load, not saved
+    first_run: bool,
+    // --keyring / PASSWORDMAKER_KEYRING: config in the system keyring, see KeyringStore
+    keyring: bool,
+}
+
+// number of edits that can be undone
+const UNDO_DEPTH: usize = 10;
+
+// Master used instead of the typed one in demo mode, keeps screenshots reproducible.
+const DEMO_MASTER: &str = "demo";
+
+// PASSWORDMAKER_FORCE_ALGORITHM: admin policy, used instead of every setting's
+// hash_algorithm when generating. The stored settings are not changed. An
+// unknown name is ignored and reported with the load errors.
+static FORCED_ALGORITHM: Lazy<Result<Option<HashAlgorithm>, String>> =
+    Lazy::new(|| match var("PASSWORDMAKER_FORCE_ALGORITHM") {
+        Ok(name) => match HashAlgorithm::from_str(&name) {
+            Ok(hash_algo) => Ok(Some(hash_algo)),
+            Err(_) => Err(name),
+        },
+        Err(_) => Ok(None),
+    });
+
+// Headless or sandboxed sessions have no clipboard, probing is not free so do it once.
+// The handle is kept, on X11 copied text is only available while its owner lives.
+static CLIPBOARD: Lazy<Mutex<Option<arboard::Clipboard>>> =
+    Lazy::new(|| Mutex::new(arboard::Clipboard::new().ok()));
+
+// Generator inputs resolved from a setting. Pwm only borrows its strings, so
+// anything computed at generation time has to be owned by something outliving it.
+struct PwmInputs {
+    hash_algorithm: HashAlgorithm,
+    use_leet: UseLeetWhenGenerating,
+    characters: String,
+    username: String,
+    modifier: String,
+    password_length: usize,
+    prefix: String,
+    suffix: String,
+    // Some in passphrase mode: the generator makes dice rolls, rendered into words
+    passphrase: Option<PassphraseStyle>,
+    force_case: ForceCase,
+    turkish_case: bool,
+}
+
+// What the generator gets for the current setting and a url, for display.
+// Built from PwmInputs so it can't drift from generation; master and modifier
+// are left out.
+pub struct ResolvedInputs {
+    pub hash_algorithm: String,
+    pub characters: String,
+    pub length: usize,
+    pub username: String,
+    pub prefix: String,
+    pub suffix: String,
+    pub used_text: String,
+}
+
+// Result of preview_import: name clashes plus the outcome of validate for every
+// incoming setting, so a file of broken profiles is noticed before it is merged
+pub struct ImportPreview {
+    pub conflicts: Vec<ImportConflict>,
+    pub valid: usize,
+    // name and error of each setting that fails validate
+    pub invalid: Vec<(String, String)>,
+}
+
+struct PassphraseStyle {
+    separator: String,
+    prefix: String,
+    suffix: String,
+}
+
+impl PwmInputs {
+    fn from_setting(setting: &PwmSetting) -> Result<PwmInputs, PwmSettingsError> {
+        PwmInputs::from_setting_at(setting, Local::now().naive_local())
+    }
+
+    // now is passed in so date dependent modifiers can be pinned
+    fn from_setting_at(
+        setting: &PwmSetting,
+        now: NaiveDateTime,
+    ) -> Result<PwmInputs, PwmSettingsError> {
+        let hash_algorithm = match *FORCED_ALGORITHM {
+            Ok(Some(hash_algo)) => hash_algo,
+            _ => match HashAlgorithm::from_str(&setting.hash_algorithm) {
+                Ok(hash_algo) => hash_algo,
+                Err(e) => return Err(PwmSettingsError::HashAlgorithmError { error: e }),
+            },
+        };
+        let use_leet = match create_use_leet_when_generating(&setting.use_leet, &setting.leet_level)
+        {
+            Ok(use_leet) => use_leet,
+            Err(e) => return Err(PwmSettingsError::LeetError { error: e }),
+        };
+        let modifier = dated_modifier(&setting.modifier, &setting.modifier_date_format, now)?;
+        let force_case = match ForceCase::from_str(&setting.force_case) {
+            Ok(force_case) => force_case,
+            Err(_) => return Err(PwmSettingsError::InvalidForceCase),
+        };
+        let (prefix, suffix) = joined_prefix_suffix(setting);
+        if setting.passphrase_mode {
+            // the Version06 algorithms output hex whatever the charset
+            if matches!(
+                hash_algorithm,
+                HashAlgorithm::Md5Version06 | HashAlgorithm::HmacMd5Version06
+            ) {
+                return Err(PwmSettingsError::PassphraseUnsupportedAlgorithm);
+            }
+            // prefix and suffix go around the words, not into the dice rolls
+            return Ok(PwmInputs {
+                hash_algorithm,
+                use_leet,
+                characters: String::from(DICE_CHARACTERS),
+                username: setting.effective_username().to_string(),
+                modifier,
+                password_length: setting.passphrase_words * ROLLS_PER_WORD,
+                prefix: String::new(),
+                suffix: String::new(),
+                passphrase: Some(PassphraseStyle {
+                    separator: setting.passphrase_separator.clone(),
+                    prefix,
+                    suffix,
+                }),
+                force_case,
+                turkish_case: setting.turkish_case,
+            });
+        }
+        // prefix and suffix are cut from the length, at least one generated character
+        // has to remain. Counted as joined with the separator, as generation uses them.
+        if prefix.chars().count() + suffix.chars().count() >= setting.effective_length() {
+            return Err(PwmSettingsError::PrefixSuffixTooLong);
+        }
+        let characters = generation_characters(setting)?;
+        // Pwm::new would reject this too, but with a message that reads like a password
+        if characters.is_empty() {
+            return Err(PwmSettingsError::EmptyCharset);
+        }
+        Ok(PwmInputs {
+            hash_algorithm,
+            use_leet,
+            characters,
+            username: setting.effective_username().to_string(),
+            modifier,
+            password_length: setting.effective_length(),
+            prefix,
+            suffix,
+            passphrase: None,
+            force_case,
+            turkish_case: setting.turkish_case,
+        })
+    }
+
+    fn pwm(&self) -> Result<Pwm<'_>, PwmSettingsError> {
+        self.pwm_with::<Hashes>()
+    }
+
+    // any hasher list, so tests can check the plumbing without the real digests
+    fn pwm_with<H: HasherList>(&self) -> Result<PasswordMaker<'_, H>, PwmSettingsError> {
+        match PasswordMaker::<H>::new(
+            self.hash_algorithm,
+            self.use_leet,
+            &self.characters,
+            &self.username,
+            &self.modifier,
+            self.password_length,
+            &self.prefix,
+            &self.suffix,
+        ) {
+            Ok(pwm) => Ok(pwm),
+            Err(e) => Err(PwmSettingsError::SettingsError { error: e }),
+        }
+    }
 }
 
 pub type Pwm<'a> = PasswordMaker<'a, Hashes>;
 
+// The separator is joined only next to a non-empty prefix or suffix. The crate
+// counts prefix and suffix towards the length, so a separator takes characters
+// away from the generated part. Empty reproduces the plain prefix and suffix.
+fn joined_prefix_suffix(setting: &PwmSetting) -> (String, String) {
+    let prefix = if setting.prefix.is_empty() {
+        String::new()
+    } else {
+        format!("{}{}", setting.prefix, setting.separator)
+    };
+    let suffix = if setting.suffix.is_empty() {
+        String::new()
+    } else {
+        format!("{}{}", setting.separator, setting.suffix)
+    };
+    (prefix, suffix)
+}
+
+// With a date format set the output depends on the current date, e.g. "%Y-%m"
+// yields a different password every month for the same url and master.
+fn dated_modifier(
+    modifier: &str,
+    date_format: &Option<String>,
+    now: NaiveDateTime,
+) -> Result<String, PwmSettingsError> {
+    let mut dated = modifier.to_string();
+    if let Some(date_format) = date_format {
+        if write!(dated, "{}", now.format(date_format)).is_err() {
+            return Err(PwmSettingsError::InvalidDateFormat);
+        }
+    }
+    Ok(dated)
+}
+
+// Works on a copy of the setting so callers don't have to hold PWM_DATA while hashing.
+pub fn generate_password(
+    setting: &PwmSetting,
+    url: String,
+    master: String,
+) -> Result<String, PwmSettingsError> {
+    let inputs = PwmInputs::from_setting(setting)?;
+    let pw = match inputs.pwm()?.generate(url, master) {
+        Ok(pw) => pw,
+        Err(e) => return Err(PwmSettingsError::GenerationError { error: e }),
+    };
+    let pw = match &inputs.passphrase {
+        Some(style) => format!(
+            "{}{}{}",
+            style.prefix,
+            dice_to_passphrase(&pw, &style.separator),
+            style.suffix
+        ),
+        None => pw,
+    };
+    Ok(fold_charset(&pw, inputs.force_case, inputs.turkish_case))
+}
+
+// Per char with the Unicode default mapping, so letters outside ASCII are folded
+// too and the length may change (ß → SS). That mapping takes i/I as a pair;
+// Turkish pairs i/İ and ı/I instead, which the table below swaps in.
+pub fn fold_charset(charset: &str, mode: ForceCase, turkish: bool) -> String {
+    match (mode, turkish) {
+        (ForceCase::None, _) => charset.to_string(),
+        (ForceCase::Upper, false) => charset.to_uppercase(),
+        (ForceCase::Lower, false) => charset.to_lowercase(),
+        (ForceCase::Upper, true) => charset
+            .chars()
+            .map(|c| match c {
+                'i' => String::from('\u{130}'),
+                '\u{131}' => String::from('I'),
+                c => c.to_uppercase().collect(),
+            })
+            .collect(),
+        (ForceCase::Lower, true) => charset
+            .chars()
+            .map(|c| match c {
+                'I' => String::from('\u{131}'),
+                '\u{130}' => String::from('i'),
+                c => c.to_lowercase().collect(),
+            })
+            .collect(),
+    }
+}
+
+// First 8 bytes of the SHA-256 of the password in hex. Enough to tell whether
+// the same password was used again, safe to log or keep where the password isn't.
+pub fn password_fingerprint(pw: &str) -> String {
+    sha2::Sha256::digest(pw.as_bytes())
+        .iter()
+        .take(8)
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// Demo mode is ignored, the canary brings its own test master.
+fn canary_password(
+    kdf: &Option<KdfConfig>,
+    setting: &PwmSetting,
+    url: &str,
+    test_master: &str,
+) -> Result<String, PwmSettingsError> {
+    let master = derive_master(kdf, test_master.to_string());
+    generate_password(setting, setting.used_text(url), master)
+}
+
+// Stretches the master with the configured KDF, hex encoded since the generator
+// takes a string. Without a KdfConfig the master is passed through unchanged.
+pub fn derive_master(kdf: &Option<KdfConfig>, mut master: String) -> String {
+    let kdf = match kdf {
+        Some(kdf) => kdf,
+        None => return master,
+    };
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<sha2::Sha256>(
+        master.as_bytes(),
+        kdf.salt.as_bytes(),
+        kdf.iterations,
+        &mut key,
+    );
+    master.zeroize();
+    let derived: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+    key.zeroize();
+    derived
+}
+
+// Checks that a generator can be built from the setting.
+pub fn validate(setting: &PwmSetting) -> Result<(), PwmSettingsError> {
+    PwmInputs::from_setting(setting)?.pwm()?;
+    Ok(())
+}
+
+// The characters a password is built from: those of the charset file if one is
+// set, else the setting's own, either way without the excluded ones.
+pub fn generation_characters(setting: &PwmSetting) -> Result<String, PwmSettingsError> {
+    match &setting.charset_file {
+        Some(path) => Ok(setting.without_excluded(&read_charset_file(path)?)),
+        None => Ok(setting.effective_characters()),
+    }
+}
+
+// Names in HashAlgorithm that this build can't generate with: not parsed back by
+// FromStr or rejected by Pwm::new with the Hashes list. Empty while every variant
+// is wired, shrinks as hashers are added. Ignores PASSWORDMAKER_FORCE_ALGORITHM.
+pub fn unsupported_algorithms() -> Vec<String> {
+    HashAlgorithm::VARIANTS
+        .iter()
+        .filter(|name| match HashAlgorithm::from_str(name) {
+            Ok(hash_algorithm) => Pwm::new(
+                hash_algorithm,
+                UseLeetWhenGenerating::NotAtAll,
+                "ab",
+                "",
+                "",
+                8,
+                "",
+                "",
+            )
+            .is_err(),
+            Err(_) => true,
+        })
+        .map(|name| String::from(*name))
+        .collect()
+}
+
+// Catches a leet mode without a usable level before generation fails on it
+pub fn leet_config_ok(setting: &PwmSetting) -> Result<(), LeetError> {
+    create_use_leet_when_generating(&setting.use_leet, &setting.leet_level)?;
+    Ok(())
+}
+
+// setting warning thresholds, lengths in characters
+const WARN_MIN_LENGTH: usize = 12;
+const WEAK_ALGORITHMS: &[&str] = &[
+    "Md4",
+    "HmacMd4",
+    "Md5",
+    "Md5Version06",
+    "HmacMd5",
+    "HmacMd5Version06",
+];
+
+fn warn_weak_algorithm(setting: &PwmSetting) -> Option<String> {
+    if WEAK_ALGORITHMS.contains(&setting.hash_algorithm.as_str()) {
+        Some(format!(
+            "{} is a weak hash algorithm",
+            setting.hash_algorithm
+        ))
+    } else {
+        None
+    }
+}
+
+fn warn_short_length(setting: &PwmSetting) -> Option<String> {
+    // passphrases are counted in words, not characters
+    if !setting.passphrase_mode && setting.effective_length() < WARN_MIN_LENGTH {
+        Some(format!("Shorter than {} characters", WARN_MIN_LENGTH))
+    } else {
+        None
+    }
+}
+
+fn warn_empty_charset(setting: &PwmSetting) -> Option<String> {
+    if !setting.passphrase_mode
+        && setting.charset_file.is_none()
+        && setting.effective_characters().is_empty()
+    {
+        Some(PwmSettingsError::EmptyCharset.to_string())
+    } else {
+        None
+    }
+}
+
+// a character given twice comes up twice as often in the password
+fn warn_duplicate_characters(setting: &PwmSetting) -> Option<String> {
+    if setting.passphrase_mode || setting.charset_file.is_some() {
+        return None;
+    }
+    let mut seen = HashSet::new();
+    let mut duplicates = String::new();
+    for c in setting.effective_characters().chars() {
+        if !seen.insert(c) && !duplicates.contains(c) {
+            duplicates.push(c);
+        }
+    }
+    if duplicates.is_empty() {
+        None
+    } else {
+        Some(format!("Characters given more than once: {}", duplicates))
+    }
+}
+
+fn warn_leet_config(setting: &PwmSetting) -> Option<String> {
+    leet_config_ok(setting).err().map(|e| e.to_string())
+}
+
+// Hints for the settings dropdown, empty when nothing looks wrong.
+fn setting_warnings_for(setting: &PwmSetting) -> Vec<String> {
+    [
+        warn_weak_algorithm,
+        warn_short_length,
+        warn_empty_charset,
+        warn_duplicate_characters,
+        warn_leet_config,
+    ]
+    .iter()
+    .filter_map(|check| check(setting))
+    .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Display)]
+pub enum MasterQuality {
+    Empty,
+    Weak,
+    Ok,
+    Strong,
+}
+
+// master quality thresholds, lengths in characters, classes are lower, upper, digit, other
+const MASTER_MIN_LENGTH: usize = 8;
+const MASTER_MIN_CLASSES: usize = 2;
+const MASTER_STRONG_LENGTH: usize = 16;
+const MASTER_STRONG_CLASSES: usize = 3;
+// well known choices that are weak whatever their length
+const COMMON_MASTERS: &[&str] = &[
+    "password",
+    "passw0rd",
+    "123456789",
+    "12345678",
+    "qwertyuiop",
+    "iloveyou",
+    "letmein",
+    "sunshine",
+    "princess",
+    "football",
+    "baseball",
+    "superman",
+    "trustno1",
+];
+
+// Soft hint only, generation works with any master. The master is not stored or logged.
+pub fn master_quality(master: &str) -> MasterQuality {
+    if master.is_empty() {
+        return MasterQuality::Empty;
+    }
+    let length = master.chars().count();
+    let classes = [
+        master.chars().any(|c| c.is_lowercase()),
+        master.chars().any(|c| c.is_uppercase()),
+        master.chars().any(|c| c.is_numeric()),
+        master.chars().any(|c| !c.is_alphanumeric()),
+    ]
+    .iter()
+    .filter(|present| **present)
+    .count();
+    if length < MASTER_MIN_LENGTH
+        || classes < MASTER_MIN_CLASSES
+        || COMMON_MASTERS.contains(&master.to_lowercase().as_str())
+    {
+        MasterQuality::Weak
+    } else if length >= MASTER_STRONG_LENGTH && classes >= MASTER_STRONG_CLASSES {
+        MasterQuality::Strong
+    } else {
+        MasterQuality::Ok
+    }
+}
+
 pub fn master_verification(master: String) -> String {
     let pwm = Pwm::new(
         HashAlgorithm::Sha256,
@@ -114,7 +668,210 @@ pub fn master_verification(master: String) -> String {
     }
 }
 
+pub fn clipboard_available() -> bool {
+    match CLIPBOARD.lock() {
+        Ok(clipboard) => clipboard.is_some(),
+        Err(_) => false,
+    }
+}
+
+pub fn copy_to_clipboard(text: &str) -> Result<(), PwmConfigError> {
+    let mut clipboard = match CLIPBOARD.lock() {
+        Ok(clipboard) => clipboard,
+        Err(_) => return Err(PwmConfigError::NoLock),
+    };
+    match clipboard.as_mut() {
+        Some(clipboard) => match clipboard.set_text(text) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(PwmConfigError::FailClipboard),
+        },
+        None => Err(PwmConfigError::NoClipboard),
+    }
+}
+
+// Summary for display only, the stored charset is never shortened.
+// Counts characters, not bytes, so symbol ranges aren't cut mid-character.
+pub fn charset_display(charset: &str, max: usize) -> String {
+    let total = charset.chars().count();
+    if total <= max {
+        return charset.to_string();
+    }
+    let shown: String = charset.chars().take(max).collect();
+    format!("{}\u{2026}(+{} more)", shown, total - max)
+}
+
+// Size of the hash the password characters are taken from.
+pub fn hash_output_bits(algo: &str) -> Option<u32> {
+    let algo = parse_variant::<HashAlgorithm>(algo, HashAlgorithm::VARIANTS).ok()?;
+    match algo {
+        HashAlgorithm::Md4
+        | HashAlgorithm::HmacMd4
+        | HashAlgorithm::Md5
+        | HashAlgorithm::Md5Version06
+        | HashAlgorithm::HmacMd5
+        | HashAlgorithm::HmacMd5Version06 => Some(128),
+        HashAlgorithm::Sha1
+        | HashAlgorithm::HmacSha1
+        | HashAlgorithm::Ripemd160
+        | HashAlgorithm::HmacRipemd160 => Some(160),
+        HashAlgorithm::Sha256 | HashAlgorithm::HmacSha256 => Some(256),
+    }
+}
+
+// The hash is read as a number of output_bits and taken modulo the charset
+// size, so the first 2^bits mod size characters come up once more than the
+// others. Returns how many characters that is, 0 when the mapping is uniform.
+pub fn charset_bias(charset_size: usize, output_bits: u32) -> usize {
+    if charset_size < 2 {
+        return 0;
+    }
+    let size = charset_size as u128;
+    let mut remainder = 1 % size;
+    for _ in 0..output_bits {
+        remainder = remainder * 2 % size;
+    }
+    remainder as usize
+}
+
+pub fn charset_bias_text(charset: &str, algo: &str) -> String {
+    let size = charset.chars().count();
+    let bits = match hash_output_bits(algo) {
+        Some(bits) => bits,
+        None => return format!("Unknown algorithm {}", algo),
+    };
+    match charset_bias(size, bits) {
+        0 => format!("Uniform: {} characters divide 2^{}", size, bits),
+        over => format!(
+            "Biased: {} of {} characters are slightly more likely (2^{} mod {})",
+            over, size, bits, size
+        ),
+    }
+}
+
+// Fills {domain}, {user} and {pass} in one pass, so a password containing
+// "{user}" is not substituted again. Other {...} text is kept as written.
+pub fn format_output(template: &str, domain: &str, user: &str, pass: &str) -> String {
+    let mut output = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+        match &rest[1..end] {
+            "domain" => output.push_str(domain),
+            "user" => output.push_str(user),
+            "pass" => output.push_str(pass),
+            _ => output.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    output.push_str(rest);
+    output
+}
+
+// The domain part as generation sees it: the crate's url parsing with only the
+// domain flag set. Text that doesn't look like a url is returned unchanged.
+pub fn extract_domain(url: &str) -> String {
+    if !is_probably_url(url) {
+        return url.to_string();
+    }
+    let flags = UrlFlags {
+        use_protocol: false,
+        use_userinfo: false,
+        use_subdomain: false,
+        use_domain: true,
+        use_params: false,
+    };
+    match url_with_flags(url, flags) {
+        domain if domain.is_empty() => url.to_string(),
+        domain => domain,
+    }
+}
+
+// Heuristic only, literal text is a valid generator input too. Accepts an optional
+// "scheme://", optional "userinfo@", then a host that is "localhost", a dotted name
+// with non-empty labels (bare domains and IPv4 included) or a bracketed IPv6
+// address, followed by an optional numeric port and anything after / ? or #.
+pub fn is_probably_url(s: &str) -> bool {
+    let s = s.trim();
+    if s.is_empty() || s.contains(char::is_whitespace) {
+        return false;
+    }
+    let rest = match s.split_once("://") {
+        Some((scheme, rest)) => {
+            if scheme.is_empty()
+                || !scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+            {
+                return false;
+            }
+            rest
+        }
+        None => s,
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or("");
+    let hostport = match authority.rsplit_once('@') {
+        Some((_, hostport)) => hostport,
+        None => authority,
+    };
+    let (host, port) = if let Some(v6) = hostport.strip_prefix('[') {
+        match v6.split_once(']') {
+            Some((addr, port)) => {
+                if addr.is_empty() || !addr.chars().all(|c| c.is_ascii_hexdigit() || c == ':') {
+                    return false;
+                }
+                return port.is_empty()
+                    || port
+                        .strip_prefix(':')
+                        .is_some_and(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()));
+            }
+            None => return false,
+        }
+    } else {
+        match hostport.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (hostport, None),
+        }
+    };
+    if let Some(port) = port {
+        if port.is_empty() || !port.chars().all(|c| c.is_ascii_digit()) {
+            return false;
+        }
+    }
+    if host.eq_ignore_ascii_case("localhost") {
+        return true;
+    }
+    host.contains('.')
+        && host.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        })
+}
+
+// --portable: the config lives next to the executable. A static rather than a
+// PwmGuiData field, the charset file is resolved without one.
+static PORTABLE: AtomicBool = AtomicBool::new(false);
+
+// a file of this name next to the executable turns on portable mode too
+const PORTABLE_SENTINEL: &str = "passwordmaker.portable";
+
+fn portable_dir() -> Option<String> {
+    let exe = std::env::current_exe().ok()?;
+    let dir = exe.parent()?;
+    if PORTABLE.load(Ordering::Relaxed) || dir.join(PORTABLE_SENTINEL).exists() {
+        Some(dir.to_string_lossy().into_owned())
+    } else {
+        None
+    }
+}
+
 fn get_home_dir() -> Result<String, VarError> {
+    if let Some(dir) = portable_dir() {
+        return Ok(dir);
+    }
     match var("XDG_CONFIG_HOME") {
         Ok(config_dir) => return Ok(config_dir),
         Err(_e) => match var("HOME") {
@@ -124,13 +881,87 @@ fn get_home_dir() -> Result<String, VarError> {
     }
 }
 
+// the one place that names the config file inside the config dir
+fn config_file_path(home: &str) -> String {
+    format!("{}/passwordmaker.toml", home)
+}
+
+// admin provided defaults for new users, PASSWORDMAKER_TEMPLATE points elsewhere
+const SYSTEM_TEMPLATE_PATH: &str = "/etc/passwordmaker/template.toml";
+
+// The first setting of the system template, None when it is missing or invalid.
+fn template_setting() -> Option<PwmSetting> {
+    let path = match var("PASSWORDMAKER_TEMPLATE") {
+        Ok(path) if !path.is_empty() => path,
+        _ => String::from(SYSTEM_TEMPLATE_PATH),
+    };
+    let config = read_config(&path).ok()?;
+    let mut setting = config.settings.get_settings().first()?.clone();
+    if validate(&setting).is_err() {
+        return None;
+    }
+    setting.created = Some(Local::now().to_rfc3339());
+    Some(setting)
+}
+
+fn compressed_config_path(home: &str) -> String {
+    format!("{}/passwordmaker.toml.gz", home)
+}
+
+// The newer of the plain and the compressed config, switching --compress on or
+// off leaves the other file behind.
+fn existing_config_path(home: &str) -> String {
+    let plain = config_file_path(home);
+    let compressed = compressed_config_path(home);
+    match (modification_time(&plain), modification_time(&compressed)) {
+        (Some(p), Some(c)) if c > p => compressed,
+        (None, Some(_)) => compressed,
+        _ => plain,
+    }
+}
+
+// FromStr with a fallback for names that differ from the variant only in case,
+// spaces or punctuation, e.g. a display form like "Before and after". The
+// dropdowns are filled from VARIANTS, so the matching variant name is parsed.
+fn parse_variant<T: FromStr>(name: &str, variants: &[&str]) -> Result<T, T::Err> {
+    let first = match T::from_str(name) {
+        Ok(parsed) => return Ok(parsed),
+        Err(e) => e,
+    };
+    let normalize = |s: &str| -> String {
+        s.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    match variants.iter().find(|v| normalize(v) == normalize(name)) {
+        Some(variant) => T::from_str(variant),
+        None => Err(first),
+    }
+}
+
+// true when every dropdown entry parses back, checked in debug builds at startup
+pub fn enum_names_parse() -> bool {
+    UseLeetWhenGenerating::VARIANTS
+        .iter()
+        .all(|name| UseLeetWhenGeneratingDiscriminants::from_str(name).is_ok())
+        && LeetLevel::VARIANTS
+            .iter()
+            .all(|name| LeetLevel::from_str(name).is_ok())
+        && HashAlgorithm::VARIANTS
+            .iter()
+            .all(|name| HashAlgorithm::from_str(name).is_ok())
+}
+
 fn create_use_leet_when_generating(
     use_leet: &str,
     leet_level: &str,
 ) -> Result<UseLeetWhenGenerating, LeetError> {
-    let ll = LeetLevel::from_str(leet_level);
-    let uldr: Result<UseLeetWhenGeneratingDiscriminants, _> =
-        UseLeetWhenGeneratingDiscriminants::from_str(use_leet);
+    let ll = parse_variant::<LeetLevel>(leet_level, LeetLevel::VARIANTS);
+    let uldr = parse_variant::<UseLeetWhenGeneratingDiscriminants>(
+        use_leet,
+        UseLeetWhenGenerating::VARIANTS,
+    );
     match uldr {
         Ok(u) => match u {
             UseLeetWhenGeneratingDiscriminants::NotAtAll => Ok(UseLeetWhenGenerating::NotAtAll),
@@ -149,33 +980,359 @@ fn create_use_leet_when_generating(
     }
 }
 
+// Expands a leading ~ to HOME and $VAR or ${VAR} anywhere, for portable paths.
+// An unset variable becomes empty, see unset_path_variables.
+pub fn expand_path(raw: &str) -> String {
+    expand_path_with_unset(raw).0
+}
+
+// The variables in raw that expand_path finds unset, reported with the load errors.
+pub fn unset_path_variables(raw: &str) -> Vec<String> {
+    expand_path_with_unset(raw).1
+}
+
+fn expand_path_with_unset(raw: &str) -> (String, Vec<String>) {
+    let mut unset = Vec::new();
+    let mut lookup = |name: &str| match var(name) {
+        Ok(value) => value,
+        Err(_) => {
+            unset.push(name.to_string());
+            String::new()
+        }
+    };
+    let mut expanded = String::new();
+    let mut rest = raw;
+    if rest == "~" || rest.starts_with("~/") {
+        expanded.push_str(&lookup("HOME"));
+        rest = &rest[1..];
+    }
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => ("", rest),
+            }
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+        if name.is_empty() {
+            expanded.push('$');
+        } else {
+            expanded.push_str(&lookup(name));
+        }
+        rest = after;
+    }
+    expanded.push_str(rest);
+    (expanded, unset)
+}
+
+// No fallback to the inline characters, a missing file must not silently
+// change the password. The trailing line break most editors add is dropped.
+fn read_charset_file(path: &str) -> Result<String, PwmSettingsError> {
+    let charset = match get_home_dir() {
+        Ok(home) => fs::read_to_string(Path::new(&home).join(expand_path(path))),
+        Err(_) => {
+            return Err(PwmSettingsError::CharsetFileError {
+                path: path.to_string(),
+            })
+        }
+    };
+    match charset {
+        Ok(charset) => Ok(charset.trim_end_matches(['\r', '\n']).to_string()),
+        Err(_) => Err(PwmSettingsError::CharsetFileError {
+            path: path.to_string(),
+        }),
+    }
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+fn read_config(path: &str) -> Result<PwmConfig, PwmConfigError> {
+    let mut vec_u8 = match fs::read(path) {
+        Ok(vec_u8) => vec_u8,
+        Err(_) => return Err(PwmConfigError::FailOpenForRead),
+    };
+    // go by content, not by extension
+    if vec_u8.starts_with(&GZIP_MAGIC) {
+        let mut plain = Vec::new();
+        if GzDecoder::new(vec_u8.as_slice())
+            .read_to_end(&mut plain)
+            .is_err()
+        {
+            return Err(PwmConfigError::Decompress);
+        }
+        vec_u8 = plain;
+    }
+    let setstr = match std::str::from_utf8(vec_u8.as_slice()) {
+        Ok(setstr) => setstr,
+        Err(_) => return Err(PwmConfigError::FailRead),
+    };
+    PwmConfig::from_toml(setstr)
+}
+
+fn modification_time(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn write_config(config: &PwmConfig, path: &str) -> Result<(), PwmConfigError> {
+    let toml = toml::to_string(config).unwrap();
+    let mut output = match File::create(path) {
+        Ok(output) => output,
+        Err(_e) => return Err(PwmConfigError::FailOpenForWrite),
+    };
+    match write!(output, "{}", toml) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(PwmConfigError::FailWrite),
+    }
+}
+
+fn write_compressed_config(config: &PwmConfig, path: &str) -> Result<(), PwmConfigError> {
+    let toml = toml::to_string(config).unwrap();
+    let output = match File::create(path) {
+        Ok(output) => output,
+        Err(_e) => return Err(PwmConfigError::FailOpenForWrite),
+    };
+    let mut encoder = GzEncoder::new(output, Compression::default());
+    if encoder.write_all(toml.as_bytes()).is_err() {
+        return Err(PwmConfigError::Compress);
+    }
+    match encoder.finish() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(PwmConfigError::Compress),
+    }
+}
+
+// Where load_settings and save_settings keep the config.
+trait SettingsStore {
+    fn read(&self) -> Result<PwmConfig, PwmConfigError>;
+    fn write(&self, config: &PwmConfig) -> Result<(), PwmConfigError>;
+    // None skips the check for changes by another instance
+    fn modified(&self) -> Option<SystemTime>;
+    // nothing stored yet, as opposed to stored but unreadable
+    fn missing(&self) -> bool;
+}
+
+#[derive(Clone)]
+struct FileStore {
+    path: String,
+    compress: bool,
+}
+
+impl SettingsStore for FileStore {
+    fn read(&self) -> Result<PwmConfig, PwmConfigError> {
+        read_config(&self.path)
+    }
+    fn write(&self, config: &PwmConfig) -> Result<(), PwmConfigError> {
+        if self.compress {
+            write_compressed_config(config, &self.path)
+        } else {
+            write_config(config, &self.path)
+        }
+    }
+    fn modified(&self) -> Option<SystemTime> {
+        modification_time(&self.path)
+    }
+    fn missing(&self) -> bool {
+        fs::metadata(&self.path).is_err_and(|e| e.kind() == ErrorKind::NotFound)
+    }
+}
+
+const KEYRING_SERVICE: &str = "passwordmaker-slint";
+const KEYRING_USER: &str = "settings";
+
+// The whole config as one toml entry of the system keyring.
+struct KeyringStore {
+    entry: keyring::Entry,
+}
+
+impl KeyringStore {
+    // None when there is no keyring or it can't be reached
+    fn open() -> Option<KeyringStore> {
+        let entry = keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER).ok()?;
+        match entry.get_password() {
+            Ok(_) | Err(keyring::Error::NoEntry) => Some(KeyringStore { entry }),
+            Err(_) => None,
+        }
+    }
+}
+
+impl SettingsStore for KeyringStore {
+    fn read(&self) -> Result<PwmConfig, PwmConfigError> {
+        match self.entry.get_password() {
+            Ok(toml) => PwmConfig::from_toml(&toml),
+            Err(keyring::Error::NoEntry) => Err(PwmConfigError::FailOpenForRead),
+            Err(_) => Err(PwmConfigError::KeyringUnavailable),
+        }
+    }
+    fn write(&self, config: &PwmConfig) -> Result<(), PwmConfigError> {
+        let toml = toml::to_string(config).unwrap();
+        match self.entry.set_password(&toml) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(PwmConfigError::KeyringUnavailable),
+        }
+    }
+    fn modified(&self) -> Option<SystemTime> {
+        None
+    }
+    fn missing(&self) -> bool {
+        matches!(self.entry.get_password(), Err(keyring::Error::NoEntry))
+    }
+}
+
+impl PwmGuiData {
+    // The keyring when enabled and reachable, otherwise the file. An unreachable
+    // keyring is reported in self.error and not tried again.
+    fn store(&mut self, file: FileStore) -> Box<dyn SettingsStore> {
+        if !self.keyring {
+            return Box::new(file);
+        }
+        match KeyringStore::open() {
+            Some(keyring) => Box::new(keyring),
+            None => {
+                self.keyring = false;
+                self.error = PwmConfigError::KeyringUnavailable;
+                Box::new(file)
+            }
+        }
+    }
+
+    // Called before every edit of the settings. A new edit drops what was undone.
+    fn push_undo(&mut self) {
+        self.record_undo(self.config.settings.clone());
+    }
+    fn record_undo(&mut self, before: PwmSettings) {
+        if self.undo_history.len() == UNDO_DEPTH {
+            self.undo_history.remove(0);
+        }
+        self.undo_history.push(before);
+        self.redo_history.clear();
+    }
+}
+
 pub trait PwmGui<'a> {
     fn new() -> Self;
     fn create_settings(&mut self);
     fn load_settings(&mut self) -> Result<(), PwmConfigError>;
+    fn load_and_validate(&mut self) -> Result<(), Vec<PwmSettingsError>>;
     fn save_settings(&mut self) -> Result<(), PwmConfigError>;
+    fn backup_settings(&mut self) -> Result<(), PwmConfigError>;
+    fn init_settings(&mut self, force: bool) -> Result<(), PwmConfigError>;
+    fn reencode_config(&mut self, target: ConfigFormat) -> Result<(), PwmConfigError>;
+    fn config_writable(&self) -> bool;
+    fn resolved_config_path(&self) -> Result<String, PwmConfigError>;
+    fn import_settings(&mut self, path: &str, mode: ImportMode) -> Result<usize, PwmConfigError>;
+    fn preview_import(&mut self, path: &str) -> Result<ImportPreview, PwmConfigError>;
+    fn apply_import(&mut self, decisions: &[ConflictDecision]) -> Result<usize, PwmConfigError>;
+    fn import_share_url(&mut self, url: &str) -> Result<usize, PwmConfigError>;
+    fn get_raw_config(&self) -> Result<String, PwmConfigError>;
+    fn write_diagnostics(&self, path: &str) -> Result<(), PwmConfigError>;
+    fn set_raw_config(&mut self, text: &str) -> Result<(), PwmConfigError>;
     fn pwm_from_setting(&'a mut self) -> Result<Pwm<'a>, PwmSettingsError>;
-    fn create_password(&mut self, url: String, master: String) -> String;
+    fn generation_setting(&self) -> Result<PwmSetting, PwmSettingsError>;
+    fn generation_master(&self, master: String) -> String;
+    fn master_kdf(&self) -> Option<KdfConfig>;
+    fn set_last_result(&mut self, pw: &str);
+    fn enable_demo_mode(&mut self);
+    fn enable_read_only(&mut self);
+    fn enable_compression(&mut self);
+    fn enable_keyring(&mut self);
+    fn enable_portable(&mut self);
+    fn get_last_result(&self) -> String;
+    fn clear_last_result(&mut self);
     fn add_setting(&mut self);
     fn delete_setting(&mut self);
     fn get_current_setting(&self) -> usize;
+    fn selection_state(&self) -> (usize, usize);
     fn set_current_setting(&mut self, current_setting: usize);
     fn get_current_setting_data(&self) -> &PwmSetting;
     fn set_current_setting_data(&mut self, setting_data: PwmSetting);
     fn get_setting_names(&self) -> Vec<SharedString>;
+    fn find_by_fingerprint(&self, fp: &str) -> Option<usize>;
+    fn settings_empty(&self) -> bool;
+    fn replace_in_field(&mut self, field: SettingField, from: &str, to: &str) -> usize;
+    fn apply_to_indices(&mut self, indices: &[usize], change: FieldChange) -> usize;
+    fn get_last_page(&self) -> &str;
+    fn get_hash_algorithms_mru(&self) -> Vec<&'static str>;
+    fn is_first_run(&self) -> bool;
+    fn set_last_page(&mut self, page: &str);
+    fn settings_view_compact(&self) -> bool;
+    fn set_settings_view_compact(&mut self, compact: bool);
+    fn set_length_preset(&mut self, length: usize) -> usize;
+    fn settings_compatible_with(&self, forbidden: &str) -> Vec<String>;
+    fn settings_using_algorithm(&self, algo: &str) -> Vec<String>;
+    fn name_exists(&self, name: &str) -> bool;
+    fn search_settings(&self, query: &str, scope: SearchScope) -> Vec<(usize, &'static str)>;
+    fn settings_equivalent(&self, a: usize, b: usize) -> bool;
+    fn setting_by_name(&self, name: &str) -> Option<PwmSetting>;
+    fn version_info(&self) -> (String, u32);
+    fn set_all_url_flags(&mut self, value: bool);
+    fn reset_url_flags(&mut self);
+    fn detect_collisions(&self, url: &str, master: &str) -> Vec<(String, String)>;
+    fn recovery_sheet(&self, url: &str, master: &str) -> String;
+    fn setting_warnings(&self, index: usize) -> Vec<String>;
+    fn rotation_due(&self, index: usize) -> bool;
+    fn share_url_qr(&self, index: usize) -> Result<Vec<u8>, PwmConfigError>;
+    fn create_password_with_algorithm(
+        &self,
+        url: &str,
+        master: &str,
+        algo: &str,
+    ) -> Result<String, PwmSettingsError>;
+    fn resolved_inputs(&self, url: &str) -> Result<ResolvedInputs, PwmSettingsError>;
+    fn generate_with_fingerprint(
+        &self,
+        url: &str,
+        master: &str,
+    ) -> Result<(String, String), PwmSettingsError>;
+    fn snapshot(&self) -> PwmSettings;
+    fn restore(&mut self, snap: PwmSettings);
+    fn undo(&mut self) -> bool;
+    fn redo(&mut self) -> bool;
+    fn leet_stages(&self, url: &str, master: &str) -> (String, String);
+    fn generate_all_leet_levels(&self, url: &str, master: &str) -> Vec<(String, String)>;
+    fn distinctness(&self, url: &str, masters: &[String]) -> (usize, usize);
+    fn set_canary(&mut self, url: &str, test_master: &str) -> Result<(), PwmSettingsError>;
+    fn check_canary(&self, index: usize) -> Option<bool>;
 }
 
 impl<'a> PwmGui<'a> for PwmGuiData {
     fn new() -> Self {
         PwmGuiData {
-            settings: PwmSettings::new(),
+            config: PwmConfig::new(),
             settings_error: PwmSettingsError::Ok,
             error: PwmConfigError::Ok,
+            inputs: None,
+            last_result: String::new(),
+            keep_config_file: false,
+            demo: false,
+            dirty: false,
+            read_only: false,
+            pending_import: None,
+            config_mtime: None,
+            undo_history: Vec::new(),
+            redo_history: Vec::new(),
+            compress: false,
+            first_run: false,
+            keyring: false,
         }
     }
 
+    // Only after a failed load. Starts over instead of adding to settings left
+    // from an earlier load, so there is always exactly one default setting.
     fn create_settings(&mut self) {
-        self.settings.add_setting();
+        self.config.settings = PwmSettings::new();
+        match template_setting() {
+            Some(setting) => {
+                self.config.settings.push_setting(setting);
+            }
+            None => self.config.settings.add_setting(),
+        }
+        self.dirty = true;
         self.settings_error = match self.pwm_from_setting() {
             Ok(_) => {
                 return;
@@ -192,32 +1349,95 @@ impl<'a> PwmGui<'a> for PwmGuiData {
                 return Err(PwmConfigError::NoHome);
             }
         };
-        let path = format!("{}/passwordmaker.toml", home);
-        let vec_u8 = match fs::read(path) {
-            Ok(vec_u8) => vec_u8,
-            Err(_) => {
-                self.create_settings();
-                return Err(PwmConfigError::FailOpenForRead);
-            }
-        };
-        let setstr = match std::str::from_utf8(vec_u8.as_slice()) {
-            Ok(setstr) => setstr,
-            Err(_) => {
-                self.create_settings();
-                return Err(PwmConfigError::FailRead);
-            }
+        let file = FileStore {
+            path: existing_config_path(&home),
+            compress: false,
         };
-        self.settings = match toml::from_str(setstr) {
-            Ok(settings) => settings,
-            Err(_) => {
+        let mut store = self.store(file.clone());
+        // first start with the keyring: read the file, the next save moves it over
+        if self.keyring && store.missing() {
+            store = Box::new(file);
+        }
+        let mtime = store.modified();
+        // missing, not unreadable: a file without permission is no first run
+        self.first_run = store.missing();
+        let config = match store.read() {
+            Ok(config) => config,
+            Err(e) => {
+                // written by a newer version, work on defaults but never save over it
+                if let PwmConfigError::ConfigTooNew = e {
+                    self.keep_config_file = true;
+                }
                 self.create_settings();
-                return Err(PwmConfigError::Str2Toml);
+                return Err(e);
             }
         };
+        self.config = config;
+        self.config_mtime = mtime;
         Ok(())
     }
 
+    // Invalid settings are kept so they can be fixed in the UI. A missing config
+    // on the first start is no error, the defaults are what the user expects.
+    fn load_and_validate(&mut self) -> Result<(), Vec<PwmSettingsError>> {
+        let mut errors = Vec::new();
+        if let Err(e) = self.load_settings() {
+            if !self.first_run {
+                errors.push(PwmSettingsError::ConfigError { error: e });
+            }
+        }
+        if let Err(name) = &*FORCED_ALGORITHM {
+            errors.push(PwmSettingsError::UnknownForcedAlgorithm { name: name.clone() });
+        }
+        errors.extend(
+            self.config
+                .settings
+                .get_settings()
+                .iter()
+                .filter_map(|setting| setting.charset_file.as_ref())
+                .flat_map(|path| {
+                    unset_path_variables(path).into_iter().map(|variable| {
+                        PwmSettingsError::UnsetPathVariable {
+                            path: path.clone(),
+                            variable,
+                        }
+                    })
+                }),
+        );
+        errors.extend(
+            self.config
+                .settings
+                .get_settings()
+                .iter()
+                .filter_map(|setting| {
+                    // checked by name, a forced algorithm would hide it from validate
+                    if !HashAlgorithm::VARIANTS.contains(&setting.hash_algorithm.as_str()) {
+                        return Some(PwmSettingsError::UnsupportedAlgorithm {
+                            name: setting.name.clone(),
+                            algorithm: setting.hash_algorithm.clone(),
+                        });
+                    }
+                    validate(setting).err()
+                }),
+        );
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
     fn save_settings(&mut self) -> Result<(), PwmConfigError> {
+        if self.demo || self.read_only {
+            return Ok(());
+        }
+        if self.keep_config_file {
+            self.error = PwmConfigError::ConfigTooNew;
+            return Err(self.error);
+        }
+        if !self.dirty {
+            return Ok(());
+        }
         let home = match get_home_dir() {
             Ok(home) => home,
             Err(_e) => {
@@ -225,80 +1445,1797 @@ impl<'a> PwmGui<'a> for PwmGuiData {
                 return Err(self.error);
             }
         };
-        let toml = toml::to_string(&self.settings).unwrap();
-        let path = format!("{}/passwordmaker.toml", home);
-
-        let mut output = match File::create(path) {
-            Ok(output) => output,
-            Err(_e) => {
-                self.error = PwmConfigError::FailOpenForWrite;
+        // a fresh XDG_CONFIG_HOME may not exist yet
+        if fs::create_dir_all(&home).is_err() {
+            self.error = PwmConfigError::FailCreateDir;
+            return Err(self.error);
+        }
+        let file = FileStore {
+            path: if self.compress {
+                compressed_config_path(&home)
+            } else {
+                config_file_path(&home)
+            },
+            compress: self.compress,
+        };
+        let store = self.store(file);
+        // another instance saved since we read the file, don't throw its changes away
+        if let (Some(loaded), Some(on_disk)) = (self.config_mtime, store.modified()) {
+            if on_disk > loaded {
+                self.error = PwmConfigError::ConcurrentModification;
                 return Err(self.error);
             }
-        };
-        match write!(output, "{}", toml) {
-            Ok(_) => Ok(()),
-            Err(_) => {
-                self.error = PwmConfigError::FailWrite;
+        }
+        match store.write(&self.config) {
+            Ok(_) => {
+                self.dirty = false;
+                self.config_mtime = store.modified();
+                Ok(())
+            }
+            Err(e) => {
+                self.error = e;
                 Err(self.error)
             }
         }
     }
 
-    fn pwm_from_setting(&'a mut self) -> Result<Pwm<'a>, PwmSettingsError> {
-        let setting = self.settings.get_current_setting_data();
-        let hash_algo = match HashAlgorithm::from_str(&setting.hash_algorithm) {
-            Ok(hash_algo) => hash_algo,
-            Err(e) => return Err(PwmSettingsError::HashAlgorithmError { error: e }),
+    fn backup_settings(&mut self) -> Result<(), PwmConfigError> {
+        let home = match get_home_dir() {
+            Ok(home) => home,
+            Err(_e) => return Err(PwmConfigError::NoHome),
         };
-        let use_leet = match create_use_leet_when_generating(&setting.use_leet, &setting.leet_level)
-        {
-            Ok(use_leet) => use_leet,
-            Err(e) => return Err(PwmSettingsError::LeetError { error: e }),
+        let path = format!("{}/passwordmaker.toml.bak", home);
+        write_config(&self.config, &path)
+    }
+
+    // A fresh config with one default setting, an existing one only with force.
+    fn init_settings(&mut self, force: bool) -> Result<(), PwmConfigError> {
+        let home = match get_home_dir() {
+            Ok(home) => home,
+            Err(_e) => return Err(PwmConfigError::NoHome),
         };
-        let pwm = match Pwm::new(
-            hash_algo,
-            use_leet,
-            &setting.characters,
-            &setting.username,
-            &setting.modifier,
-            setting.password_length,
-            &setting.prefix,
-            &setting.suffix,
-        ) {
-            Ok(pwm) => Ok(pwm),
-            Err(e) => Err(PwmSettingsError::SettingsError { error: e }),
+        let existing = existing_config_path(&home);
+        if Path::new(&existing).exists() && !force {
+            return Err(PwmConfigError::ConfigExists);
+        }
+        self.config = PwmConfig::new();
+        self.config.settings.add_setting();
+        self.keep_config_file = false;
+        // overwriting on purpose, skip the concurrent modification check
+        self.config_mtime = None;
+        self.dirty = true;
+        self.save_settings()
+    }
+
+    // Rewrites the stored config in the target format, unsaved edits are saved
+    // later in that format too. The old file is copied to its own backup, so the
+    // one of import and restore is left alone, and removed once the new file is
+    // written. The backup goes on every outcome; on a failure the new file goes
+    // too, the old one stays the config and the format is not changed.
+    fn reencode_config(&mut self, target: ConfigFormat) -> Result<(), PwmConfigError> {
+        if self.demo || self.read_only {
+            return Ok(());
+        }
+        if self.keep_config_file {
+            return Err(PwmConfigError::ConfigTooNew);
+        }
+        if self.keyring {
+            return Err(PwmConfigError::ConfigInKeyring);
+        }
+        let home = match get_home_dir() {
+            Ok(home) => home,
+            Err(_e) => return Err(PwmConfigError::NoHome),
         };
-        pwm
+        let old_path = existing_config_path(&home);
+        let (new_path, compress) = match target {
+            ConfigFormat::Plain => (config_file_path(&home), false),
+            ConfigFormat::Compressed => (compressed_config_path(&home), true),
+        };
+        if old_path == new_path {
+            self.compress = compress;
+            return Ok(());
+        }
+        let stored = read_config(&old_path)?;
+        let backup = format!("{}/passwordmaker.toml.reencode.bak", home);
+        let result = write_config(&stored, &backup)
+            .and_then(|_| {
+                if compress {
+                    write_compressed_config(&stored, &new_path)
+                } else {
+                    write_config(&stored, &new_path)
+                }
+            })
+            .and_then(|_| fs::remove_file(&old_path).map_err(|_| PwmConfigError::FailWrite));
+        if result.is_err() {
+            let _ = fs::remove_file(&new_path);
+        }
+        let _ = fs::remove_file(&backup);
+        result?;
+        self.compress = compress;
+        self.config_mtime = modification_time(&new_path);
+        Ok(())
     }
 
-    fn create_password(&mut self, url: String, master: String) -> String {
-        match self.pwm_from_setting() {
-            Ok(pwm) => match pwm.generate(url, master) {
-                Ok(pw) => pw,
-                Err(e) => e.to_string(),
-            },
-            Err(e) => e.to_string(),
+    // False when save_settings would not write the file. An existing config is
+    // opened for writing without truncating it, otherwise a probe file is created
+    // in the config dir and removed again.
+    fn config_writable(&self) -> bool {
+        if self.demo || self.read_only || self.keep_config_file {
+            return false;
+        }
+        let home = match get_home_dir() {
+            Ok(home) => home,
+            Err(_) => return false,
+        };
+        let path = if self.compress {
+            compressed_config_path(&home)
+        } else {
+            config_file_path(&home)
+        };
+        if Path::new(&path).exists() {
+            return OpenOptions::new().write(true).open(&path).is_ok();
         }
+        // save_settings creates a missing config dir, probe where it would start
+        let mut dir = Path::new(&home);
+        while !dir.exists() {
+            dir = match dir.parent() {
+                Some(parent) => parent,
+                None => return false,
+            };
+        }
+        let probe = dir.join(".passwordmaker.toml.probe");
+        match OpenOptions::new().write(true).create_new(true).open(&probe) {
+            Ok(_) => fs::remove_file(&probe).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    // The path load and save use, made absolute against the working directory
+    // when XDG_CONFIG_HOME is relative.
+    fn resolved_config_path(&self) -> Result<String, PwmConfigError> {
+        let home = match get_home_dir() {
+            Ok(home) => home,
+            Err(_) => return Err(PwmConfigError::NoHome),
+        };
+        let path = PathBuf::from(existing_config_path(&home));
+        if path.is_absolute() {
+            return Ok(path.to_string_lossy().into_owned());
+        }
+        match std::env::current_dir() {
+            Ok(cwd) => Ok(cwd.join(path).to_string_lossy().into_owned()),
+            Err(_) => Err(PwmConfigError::NoHome),
+        }
+    }
+
+    fn import_settings(&mut self, path: &str, mode: ImportMode) -> Result<usize, PwmConfigError> {
+        // only the settings are taken over, not the other file's app preferences
+        let imported = read_config(&expand_path(path))?.settings;
+        let count = imported.len();
+        // keep the replaced collection recoverable, before anything is changed
+        if let ImportMode::Replace = mode {
+            self.backup_settings()?;
+        }
+        self.push_undo();
+        match mode {
+            ImportMode::Append => self.config.settings.append_settings(imported),
+            ImportMode::Replace => self.config.settings = imported,
+        }
+        self.dirty = true;
+        Ok(count)
+    }
+
+    // Reads the file and reports name clashes, the settings stay untouched
+    // until apply_import.
+    fn preview_import(&mut self, path: &str) -> Result<ImportPreview, PwmConfigError> {
+        let imported = read_config(&expand_path(path))?.settings;
+        let conflicts = self.config.settings.import_conflicts(&imported);
+        let invalid: Vec<(String, String)> = imported
+            .get_settings()
+            .iter()
+            .filter_map(|setting| {
+                validate(setting)
+                    .err()
+                    .map(|e| (setting.name.clone(), e.to_string()))
+            })
+            .collect();
+        let valid = imported.len() - invalid.len();
+        self.pending_import = Some(imported);
+        Ok(ImportPreview {
+            conflicts,
+            valid,
+            invalid,
+        })
+    }
+
+    fn apply_import(&mut self, decisions: &[ConflictDecision]) -> Result<usize, PwmConfigError> {
+        let imported = match self.pending_import.take() {
+            Some(imported) => imported,
+            None => return Err(PwmConfigError::NoPendingImport),
+        };
+        self.push_undo();
+        let count = self.config.settings.merge_settings(imported, decisions);
+        self.dirty = true;
+        Ok(count)
+    }
+
+    // The link carries no modifier or notes, those stay at their PWM_DEFAULT values.
+    fn import_share_url(&mut self, url: &str) -> Result<usize, PwmConfigError> {
+        let mut setting = PwmSetting::from_share_url(url)?;
+        setting.created = Some(Local::now().to_rfc3339());
+        self.push_undo();
+        let index = self.config.settings.push_setting(setting);
+        self.dirty = true;
+        Ok(index)
+    }
+
+    fn get_raw_config(&self) -> Result<String, PwmConfigError> {
+        match toml::to_string(&self.config) {
+            Ok(toml) => Ok(toml),
+            Err(_) => Err(PwmConfigError::Toml2Str),
+        }
+    }
+
+    // Version info and the redacted config, safe to attach to a bug report.
+    fn write_diagnostics(&self, path: &str) -> Result<(), PwmConfigError> {
+        let (app, config_version) = self.version_info();
+        let toml = match toml::to_string(&self.config.redacted()) {
+            Ok(toml) => toml,
+            Err(_) => return Err(PwmConfigError::Toml2Str),
+        };
+        let mut output = match File::create(expand_path(path)) {
+            Ok(output) => output,
+            Err(_e) => return Err(PwmConfigError::FailOpenForWrite),
+        };
+        match write!(
+            output,
+            "# passwordmaker-slint {}, config version {}\n{}",
+            app, config_version, toml
+        ) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(PwmConfigError::FailWrite),
+        }
+    }
+
+    // the in-memory settings are only replaced when the whole text parses
+    fn set_raw_config(&mut self, text: &str) -> Result<(), PwmConfigError> {
+        let config = PwmConfig::from_toml(text)?;
+        self.push_undo();
+        self.config = config;
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn pwm_from_setting(&'a mut self) -> Result<Pwm<'a>, PwmSettingsError> {
+        // get_current_setting_data falls back to PWM_DEFAULT, don't generate from that
+        if self.config.settings.is_empty() {
+            return Err(PwmSettingsError::NoSettingSelected);
+        }
+        let inputs = PwmInputs::from_setting(self.config.settings.get_current_setting_data())?;
+        self.inputs.insert(inputs).pwm()
+    }
+
+    fn generation_setting(&self) -> Result<PwmSetting, PwmSettingsError> {
+        // get_current_setting_data falls back to PWM_DEFAULT, don't generate from that
+        if self.config.settings.is_empty() {
+            return Err(PwmSettingsError::NoSettingSelected);
+        }
+        Ok(self.config.settings.get_current_setting_data().clone())
+    }
+    fn generation_master(&self, master: String) -> String {
+        if self.demo {
+            DEMO_MASTER.to_string()
+        } else {
+            master
+        }
+    }
+    fn master_kdf(&self) -> Option<KdfConfig> {
+        self.config.global.master_kdf()
+    }
+    fn set_last_result(&mut self, pw: &str) {
+        self.last_result.zeroize();
+        self.last_result = pw.to_string();
+    }
+    fn enable_demo_mode(&mut self) {
+        self.demo = true;
+    }
+    fn enable_read_only(&mut self) {
+        self.read_only = true;
+    }
+    // Call before loading. Both load and save then use the executable's dir,
+    // a read-only dir shows up as FailOpenForWrite on save.
+    fn enable_portable(&mut self) {
+        PORTABLE.store(true, Ordering::Relaxed);
+    }
+    // Call before loading. Without a reachable keyring the file is used and
+    // the error says so.
+    fn enable_keyring(&mut self) {
+        self.keyring = true;
+    }
+    fn enable_compression(&mut self) {
+        self.compress = true;
+        // the next save writes the compressed file even without edits
+        self.dirty = true;
+    }
+    fn get_last_result(&self) -> String {
+        self.last_result.clone()
+    }
+    fn clear_last_result(&mut self) {
+        self.last_result.zeroize();
     }
     fn add_setting(&mut self) {
-        self.settings.add_setting();
+        self.push_undo();
+        self.config.settings.add_setting();
+        self.dirty = true;
     }
     fn delete_setting(&mut self) {
-        self.settings.delete_setting();
+        self.push_undo();
+        self.config.settings.delete_setting();
+        self.dirty = true;
     }
     fn get_current_setting(&self) -> usize {
-        self.settings.get_current_setting()
+        self.config.settings.get_current_setting()
+    }
+    fn selection_state(&self) -> (usize, usize) {
+        self.config.settings.selection_state()
     }
     fn set_current_setting(&mut self, current_setting: usize) {
-        self.settings.set_current_setting(current_setting)
+        self.config.settings.set_current_setting(current_setting);
+        self.dirty = true;
     }
     fn get_current_setting_data(&self) -> &PwmSetting {
-        self.settings.get_current_setting_data()
+        self.config.settings.get_current_setting_data()
     }
     fn set_current_setting_data(&mut self, setting_data: PwmSetting) {
-        self.settings.set_current_setting_data(setting_data)
+        // the UI writes the setting back unchanged too, that is no edit.
+        // created and canary are not in the Slint struct, the stored ones are kept.
+        let current = self.config.settings.get_current_setting_data();
+        let incoming = PwmSetting {
+            created: current.created.clone(),
+            canary: current.canary.clone(),
+            ..setting_data.clone()
+        };
+        let algorithm_changed = incoming.hash_algorithm != current.hash_algorithm;
+        if incoming != *current {
+            self.push_undo();
+        }
+        if algorithm_changed {
+            self.config
+                .global
+                .record_algorithm(&setting_data.hash_algorithm);
+        }
+        self.config.settings.set_current_setting_data(setting_data);
+        self.dirty = true;
     }
     fn get_setting_names(&self) -> Vec<SharedString> {
-        self.settings.get_setting_names()
+        self.config.settings.get_setting_names()
+    }
+    fn find_by_fingerprint(&self, fp: &str) -> Option<usize> {
+        self.config.settings.find_by_fingerprint(fp)
+    }
+    fn settings_empty(&self) -> bool {
+        self.config.settings.is_empty()
+    }
+    fn apply_to_indices(&mut self, indices: &[usize], change: FieldChange) -> usize {
+        let before = self.config.settings.clone();
+        let changed = self.config.settings.apply_to_indices(indices, change);
+        if changed > 0 {
+            self.record_undo(before);
+            self.dirty = true;
+        }
+        changed
+    }
+    fn replace_in_field(&mut self, field: SettingField, from: &str, to: &str) -> usize {
+        let before = self.config.settings.clone();
+        let changed = self.config.settings.replace_in_field(field, from, to);
+        if changed > 0 {
+            self.record_undo(before);
+            self.dirty = true;
+        }
+        changed
+    }
+    // HashAlgorithm::VARIANTS with the recently picked ones first, each once;
+    // record_algorithm keeps the recent list free of repeats. Unknown names in
+    // the config are skipped.
+    fn get_hash_algorithms_mru(&self) -> Vec<&'static str> {
+        let recent = self
+            .config
+            .global
+            .recent_algorithms()
+            .iter()
+            .filter_map(|algo| HashAlgorithm::VARIANTS.iter().find(|v| *v == algo));
+        let mut algos: Vec<&'static str> = Vec::new();
+        for algo in recent.chain(HashAlgorithm::VARIANTS.iter()).copied() {
+            if !algos.contains(&algo) {
+                algos.push(algo);
+            }
+        }
+        algos
+    }
+    fn is_first_run(&self) -> bool {
+        self.first_run
+    }
+    fn get_last_page(&self) -> &str {
+        self.config.global.get_last_page()
+    }
+    fn set_last_page(&mut self, page: &str) {
+        self.config.global.set_last_page(page);
+        self.dirty = true;
+    }
+    fn settings_view_compact(&self) -> bool {
+        self.config.global.settings_view_compact()
+    }
+    fn set_settings_view_compact(&mut self, compact: bool) {
+        self.config.global.set_settings_view_compact(compact);
+        self.dirty = true;
+    }
+    fn set_length_preset(&mut self, length: usize) -> usize {
+        self.push_undo();
+        self.dirty = true;
+        self.config.settings.set_password_length(length)
+    }
+    fn settings_compatible_with(&self, forbidden: &str) -> Vec<String> {
+        self.config.settings.settings_compatible_with(forbidden)
+    }
+    fn settings_using_algorithm(&self, algo: &str) -> Vec<String> {
+        self.config.settings.settings_using_algorithm(algo)
+    }
+    fn name_exists(&self, name: &str) -> bool {
+        self.config.settings.name_exists(name)
+    }
+    fn search_settings(&self, query: &str, scope: SearchScope) -> Vec<(usize, &'static str)> {
+        self.config.settings.search_settings(query, scope)
+    }
+    fn settings_equivalent(&self, a: usize, b: usize) -> bool {
+        let settings = self.config.settings.get_settings();
+        match (settings.get(a), settings.get(b)) {
+            (Some(a), Some(b)) => generation_equivalent(a, b),
+            _ => false,
+        }
+    }
+    fn setting_by_name(&self, name: &str) -> Option<PwmSetting> {
+        self.config
+            .settings
+            .get_settings()
+            .iter()
+            .find(|s| s.name == name)
+            .cloned()
+    }
+    // app version and the layout version of the loaded config, for bug reports
+    fn version_info(&self) -> (String, u32) {
+        (
+            String::from(env!("CARGO_PKG_VERSION")),
+            self.config.settings.config_version(),
+        )
+    }
+    fn set_all_url_flags(&mut self, value: bool) {
+        self.push_undo();
+        self.config.settings.set_all_url_flags(value);
+        self.dirty = true;
+    }
+    fn reset_url_flags(&mut self) {
+        self.push_undo();
+        self.config.settings.reset_url_flags();
+        self.dirty = true;
+    }
+    // Pairs of settings that give the same password for url. Settings that fail
+    // to generate are left out. Uses the free generator, current_setting is not touched.
+    fn detect_collisions(&self, url: &str, master: &str) -> Vec<(String, String)> {
+        let master = derive_master(
+            &self.master_kdf(),
+            self.generation_master(master.to_string()),
+        );
+        let generated: Vec<(&str, String)> = self
+            .config
+            .settings
+            .get_settings()
+            .iter()
+            .filter_map(|setting| {
+                let used_text = setting.used_text(url);
+                generate_password(setting, used_text, master.clone())
+                    .ok()
+                    .map(|pw| (setting.name.as_str(), pw))
+            })
+            .collect();
+        let mut collisions = Vec::new();
+        for (i, (name_a, pw_a)) in generated.iter().enumerate() {
+            for (name_b, pw_b) in &generated[i + 1..] {
+                if pw_a == pw_b {
+                    collisions.push((name_a.to_string(), name_b.to_string()));
+                }
+            }
+        }
+        collisions
+    }
+    // One block per setting with a sample password for url, to check later that
+    // the setting still reproduces. The samples come from the master the user typed,
+    // so the sheet is as sensitive as the passwords: shown only, never written to disk.
+    // Like detect_collisions it uses the free generator, current_setting stays as is.
+    fn recovery_sheet(&self, url: &str, master: &str) -> String {
+        let master = derive_master(
+            &self.master_kdf(),
+            self.generation_master(master.to_string()),
+        );
+        let mut sheet = format!("Sample url: {}\n", url);
+        for setting in self.config.settings.get_settings() {
+            let used_text = setting.used_text(url);
+            let sample = match generate_password(setting, used_text, master.clone()) {
+                Ok(pw) => pw,
+                Err(e) => format!("!error: {}", e),
+            };
+            let _ = write!(
+                sheet,
+                "\nSetting:   {}\nAlgorithm: {}\nLength:    {}\nSample:    {}\n",
+                setting.name,
+                setting.hash_algorithm,
+                setting.effective_length(),
+                sample
+            );
+        }
+        sheet
+    }
+    // One-off generation with another algorithm on a copy of the current setting,
+    // the stored one is not changed. PASSWORDMAKER_FORCE_ALGORITHM still wins.
+    fn create_password_with_algorithm(
+        &self,
+        url: &str,
+        master: &str,
+        algo: &str,
+    ) -> Result<String, PwmSettingsError> {
+        if let Err(e) = HashAlgorithm::from_str(algo) {
+            return Err(PwmSettingsError::HashAlgorithmError { error: e });
+        }
+        let mut setting = self.generation_setting()?;
+        setting.hash_algorithm = algo.to_string();
+        let master = derive_master(
+            &self.master_kdf(),
+            self.generation_master(master.to_string()),
+        );
+        let used_text = setting.used_text(url);
+        generate_password(&setting, used_text, master)
+    }
+    // The password and its password_fingerprint.
+    fn generate_with_fingerprint(
+        &self,
+        url: &str,
+        master: &str,
+    ) -> Result<(String, String), PwmSettingsError> {
+        let setting = self.generation_setting()?;
+        let master = derive_master(
+            &self.master_kdf(),
+            self.generation_master(master.to_string()),
+        );
+        let pw = generate_password(&setting, setting.used_text(url), master)?;
+        let fingerprint = password_fingerprint(&pw);
+        Ok((pw, fingerprint))
+    }
+    // empty for an index past the end
+    fn setting_warnings(&self, index: usize) -> Vec<String> {
+        match self.config.settings.get_settings().get(index) {
+            Some(setting) => setting_warnings_for(setting),
+            None => Vec::new(),
+        }
+    }
+    fn rotation_due(&self, index: usize) -> bool {
+        match self.config.settings.get_settings().get(index) {
+            Some(setting) => setting.rotation_due(),
+            None => false,
+        }
+    }
+    // PNG of the share link, which leaves out the modifier and the notes
+    fn share_url_qr(&self, index: usize) -> Result<Vec<u8>, PwmConfigError> {
+        let url = match self.config.settings.get_settings().get(index) {
+            Some(setting) => setting.to_share_url(),
+            None => return Err(PwmConfigError::NoSuchSetting),
+        };
+        let code = match QrCode::new(url.as_bytes()) {
+            Ok(code) => code,
+            Err(QrError::DataTooLong) => return Err(PwmConfigError::ShareUrlTooLong),
+            Err(_) => return Err(PwmConfigError::FailQrCode),
+        };
+        let qr = code.render::<Luma<u8>>().build();
+        let mut png = Vec::new();
+        match DynamicImage::ImageLuma8(qr).write_to(&mut Cursor::new(&mut png), ImageFormat::Png) {
+            Ok(_) => Ok(png),
+            Err(_) => Err(PwmConfigError::FailQrCode),
+        }
+    }
+    fn resolved_inputs(&self, url: &str) -> Result<ResolvedInputs, PwmSettingsError> {
+        let setting = self.generation_setting()?;
+        let inputs = PwmInputs::from_setting(&setting)?;
+        Ok(ResolvedInputs {
+            hash_algorithm: format!("{:?}", inputs.hash_algorithm),
+            characters: inputs.characters,
+            length: inputs.password_length,
+            username: inputs.username,
+            prefix: inputs.prefix,
+            suffix: inputs.suffix,
+            used_text: setting.used_text(url),
+        })
+    }
+    fn snapshot(&self) -> PwmSettings {
+        self.config.settings.clone()
+    }
+    // Remembers what the current setting gives now for url and test_master.
+    fn set_canary(&mut self, url: &str, test_master: &str) -> Result<(), PwmSettingsError> {
+        let setting = self.generation_setting()?;
+        let pw = canary_password(&self.master_kdf(), &setting, url, test_master)?;
+        self.push_undo();
+        self.config.settings.set_canary(Some(Canary {
+            url: url.to_string(),
+            test_master: test_master.to_string(),
+            expected: Canary::hash_password(&pw),
+        }));
+        self.dirty = true;
+        Ok(())
+    }
+    // None without a canary, false also when the setting no longer generates
+    fn check_canary(&self, index: usize) -> Option<bool> {
+        let setting = self.config.settings.get_settings().get(index)?;
+        let canary = setting.canary.as_ref()?;
+        match canary_password(
+            &self.master_kdf(),
+            setting,
+            &canary.url,
+            &canary.test_master,
+        ) {
+            Ok(pw) => Some(Canary::hash_password(&pw) == canary.expected),
+            Err(_) => Some(false),
+        }
+    }
+    // A restore is an edit like any other: it can be undone and marks the
+    // settings dirty, so the restored state is what gets saved on exit.
+    fn restore(&mut self, snap: PwmSettings) {
+        self.push_undo();
+        self.config.settings = snap;
+        self.dirty = true;
+    }
+    // Both return false when there is nothing to go back or forward to.
+    fn undo(&mut self) -> bool {
+        let previous = match self.undo_history.pop() {
+            Some(previous) => previous,
+            None => return false,
+        };
+        let current = std::mem::replace(&mut self.config.settings, previous);
+        self.redo_history.push(current);
+        self.dirty = true;
+        true
+    }
+    fn redo(&mut self) -> bool {
+        let next = match self.redo_history.pop() {
+            Some(next) => next,
+            None => return false,
+        };
+        let current = std::mem::replace(&mut self.config.settings, next);
+        self.undo_history.push(current);
+        self.dirty = true;
+        true
+    }
+    // passwordmaker-rs only exposes the final password, not the text between the
+    // stages. Approximated by generating once with leet Before only and once with
+    // After only at the setting's level; BeforeAndAfter applies both. Empty strings
+    // when the current setting can't generate.
+    fn leet_stages(&self, url: &str, master: &str) -> (String, String) {
+        let setting = match self.generation_setting() {
+            Ok(setting) => setting,
+            Err(_) => return (String::new(), String::new()),
+        };
+        let master = derive_master(
+            &self.master_kdf(),
+            self.generation_master(master.to_string()),
+        );
+        let used_text = setting.used_text(url);
+        let stage = |use_leet: &str| {
+            let mut staged = setting.clone();
+            staged.use_leet = use_leet.to_string();
+            generate_password(&staged, used_text.clone(), master.clone()).unwrap_or_default()
+        };
+        (stage("Before"), stage("After"))
+    }
+
+    // Works on a copy, the setting keeps its leet level. With use_leet
+    // "NotAtAll" all levels give the same password.
+    fn generate_all_leet_levels(&self, url: &str, master: &str) -> Vec<(String, String)> {
+        let setting = match self.generation_setting() {
+            Ok(setting) => setting,
+            Err(_) => return Vec::new(),
+        };
+        let master = derive_master(
+            &self.master_kdf(),
+            self.generation_master(master.to_string()),
+        );
+        let used_text = setting.used_text(url);
+        LeetLevel::VARIANTS
+            .iter()
+            .map(|level| {
+                let mut leveled = setting.clone();
+                leveled.leet_level = level.to_string();
+                let pw = generate_password(&leveled, used_text.clone(), master.clone())
+                    .unwrap_or_default();
+                (level.to_string(), pw)
+            })
+            .collect()
+    }
+
+    // (distinct passwords, masters) for the current setting and url. Memory only,
+    // a master failing to generate counts as not distinct.
+    fn distinctness(&self, url: &str, masters: &[String]) -> (usize, usize) {
+        let setting = match self.generation_setting() {
+            Ok(setting) => setting,
+            Err(_) => return (0, masters.len()),
+        };
+        let used_text = setting.used_text(url);
+        let mut passwords: HashSet<String> = masters
+            .iter()
+            .filter_map(|master| {
+                let master = derive_master(
+                    &self.master_kdf(),
+                    self.generation_master(master.to_string()),
+                );
+                generate_password(&setting, used_text.clone(), master).ok()
+            })
+            .collect();
+        let distinct = passwords.len();
+        passwords.drain().for_each(|mut pw| pw.zeroize());
+        (distinct, masters.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pwm_settings::{MAX_PASSWORD_LENGTH, PWM_DEFAULT, SUPPORTED_CONFIG_VERSION};
+
+    // deterministic stand-in for the digests: the input folded into N bytes
+    struct TestHash<const N: usize>;
+    impl<const N: usize> Hasher for TestHash<N> {
+        type Output = [u8; N];
+        fn hash(data: &[u8]) -> Self::Output {
+            let mut output = [0u8; N];
+            for (i, byte) in data.iter().enumerate() {
+                output[i % N] = output[i % N].wrapping_mul(31).wrapping_add(*byte);
+            }
+            output
+        }
+    }
+    impl passwordmaker_rs::Md4 for TestHash<16> {}
+    impl passwordmaker_rs::Md5 for TestHash<16> {}
+    impl passwordmaker_rs::Sha1 for TestHash<20> {}
+    impl passwordmaker_rs::Ripemd160 for TestHash<20> {}
+    impl passwordmaker_rs::Sha256 for TestHash<32> {}
+
+    struct TestHashes;
+    impl HasherList for TestHashes {
+        type MD4 = TestHash<16>;
+        type MD5 = TestHash<16>;
+        type SHA1 = TestHash<20>;
+        type SHA256 = TestHash<32>;
+        type RIPEMD160 = TestHash<20>;
+    }
+
+    fn test_generate(setting: &PwmSetting) -> String {
+        let inputs = match PwmInputs::from_setting(setting) {
+            Ok(inputs) => inputs,
+            Err(e) => panic!("invalid setting: {}", e),
+        };
+        let pwm = match inputs.pwm_with::<TestHashes>() {
+            Ok(pwm) => pwm,
+            Err(e) => panic!("rejected inputs: {}", e),
+        };
+        match pwm
+            .generate(String::from("example.com"), String::from("master"))
+            .map_err(|error| PwmSettingsError::GenerationError { error })
+        {
+            Ok(pw) => pw,
+            Err(e) => panic!("generation failed: {}", e),
+        }
+    }
+
+    #[test]
+    fn settings_reach_the_generator() {
+        let mut setting = PWM_DEFAULT.clone();
+        setting.characters = String::from("xy");
+        setting.password_length = 13;
+        let password = test_generate(&setting);
+        assert_eq!(password.chars().count(), 13);
+        assert!(password.chars().all(|c| "xy".contains(c)), "{password}");
+
+        setting.prefix = String::from("PRE");
+        setting.suffix = String::from("SUF");
+        let password = test_generate(&setting);
+        assert_eq!(password.chars().count(), 13);
+        assert!(password.starts_with("PRE"), "{password}");
+        assert!(password.ends_with("SUF"), "{password}");
+        assert!(
+            password[3..10].chars().all(|c| "xy".contains(c)),
+            "{password}"
+        );
+    }
+
+    #[test]
+    fn every_algorithm_generates_with_test_hashes() {
+        for name in HashAlgorithm::VARIANTS {
+            let mut setting = PWM_DEFAULT.clone();
+            setting.hash_algorithm = name.to_string();
+            assert!(!test_generate(&setting).is_empty(), "{name}");
+        }
+    }
+
+    // PwmSettingsError has no Debug, so no unwrap
+    fn generated(result: Result<String, PwmSettingsError>) -> String {
+        match result {
+            Ok(pw) => pw,
+            Err(e) => panic!("generation failed: {}", e),
+        }
+    }
+
+    // tests that change the environment, e.g. XDG_CONFIG_HOME, take turns
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    fn env_lock() -> std::sync::MutexGuard<'static, ()> {
+        ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // an empty config dir for one test, set as XDG_CONFIG_HOME
+    fn temp_home(name: &str) -> PathBuf {
+        let home = std::env::temp_dir().join(format!("pwm-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&home);
+        fs::create_dir_all(&home).unwrap();
+        std::env::set_var("XDG_CONFIG_HOME", &home);
+        home
+    }
+
+    // an environment variable set for one test, the old value is back on drop,
+    // also when an assertion fails
+    struct EnvVar {
+        name: &'static str,
+        old: Option<std::ffi::OsString>,
+    }
+
+    impl Drop for EnvVar {
+        fn drop(&mut self) {
+            match &self.old {
+                Some(old) => std::env::set_var(self.name, old),
+                None => std::env::remove_var(self.name),
+            }
+        }
+    }
+
+    fn set_env(name: &'static str, value: impl AsRef<std::ffi::OsStr>) -> EnvVar {
+        let old = std::env::var_os(name);
+        std::env::set_var(name, value);
+        EnvVar { name, old }
+    }
+
+    fn named_settings(names: &[&str]) -> PwmConfig {
+        let mut config = PwmConfig::new();
+        for name in names {
+            let mut setting = PWM_DEFAULT.clone();
+            setting.name = name.to_string();
+            config.settings.push_setting(setting);
+        }
+        config
+    }
+
+    fn setting_names(pwm: &PwmGuiData) -> Vec<String> {
+        pwm.config
+            .settings
+            .get_settings()
+            .iter()
+            .map(|s| s.name.clone())
+            .collect()
+    }
+
+    fn generate(setting: &PwmSetting) -> String {
+        generated(generate_password(
+            setting,
+            String::from("example.com"),
+            String::from("master"),
+        ))
+    }
+
+    #[test]
+    fn excluding_a_character_not_in_the_charset_changes_nothing() {
+        let mut setting = PWM_DEFAULT.clone();
+        setting.characters = String::from("abcdef");
+        let plain = generate(&setting);
+        setting.excluded_characters = String::from("z");
+        assert_eq!(setting.effective_characters(), "abcdef");
+        assert_eq!(generate(&setting), plain);
+    }
+
+    #[test]
+    fn excluded_characters_are_not_generated() {
+        let mut setting = PWM_DEFAULT.clone();
+        setting.characters = String::from("abcdef");
+        setting.excluded_characters = String::from("ace");
+        assert!(generate(&setting).chars().all(|c| "bdf".contains(c)));
+    }
+
+    #[test]
+    fn excluding_every_character_is_an_error() {
+        let mut setting = PWM_DEFAULT.clone();
+        setting.characters = String::from("ab");
+        setting.excluded_characters = String::from("ba");
+        assert!(matches!(
+            generate_password(
+                &setting,
+                String::from("example.com"),
+                String::from("master")
+            ),
+            Err(PwmSettingsError::EmptyCharset)
+        ));
+    }
+
+    #[test]
+    fn import_appends_or_replaces_after_a_backup() {
+        let _env = env_lock();
+        let home = temp_home("import");
+        let file = home.join("other.toml");
+        let file = file.to_str().unwrap();
+        write_config(&named_settings(&["imported"]), file).unwrap();
+        let mut pwm = PwmGuiData::new();
+        pwm.config = named_settings(&["mine"]);
+
+        assert_eq!(pwm.import_settings(file, ImportMode::Append).unwrap(), 1);
+        assert_eq!(setting_names(&pwm), ["mine", "imported"]);
+
+        pwm.import_settings(file, ImportMode::Replace).unwrap();
+        assert_eq!(setting_names(&pwm), ["imported"]);
+        let backup = read_config(home.join("passwordmaker.toml.bak").to_str().unwrap()).unwrap();
+        assert_eq!(backup.settings.len(), 2);
+    }
+
+    #[test]
+    fn failed_replace_backup_leaves_the_settings_alone() {
+        let _env = env_lock();
+        let home = temp_home("import-no-backup");
+        let file = home.join("other.toml");
+        let file = file.to_str().unwrap();
+        write_config(&named_settings(&["imported"]), file).unwrap();
+        // a file where the config dir should be, the backup can't be written
+        let blocked = home.join("blocked");
+        fs::write(&blocked, "").unwrap();
+        std::env::set_var("XDG_CONFIG_HOME", &blocked);
+        let mut pwm = PwmGuiData::new();
+        pwm.config = named_settings(&["mine"]);
+
+        assert!(pwm.import_settings(file, ImportMode::Replace).is_err());
+        assert_eq!(setting_names(&pwm), ["mine"]);
+        assert!(pwm.undo_history.is_empty());
+    }
+
+    #[test]
+    fn newer_config_is_neither_loaded_nor_overwritten() {
+        let _env = env_lock();
+        let home = temp_home("too-new");
+        let path = home.join("passwordmaker.toml");
+        // a layout this version can't parse
+        let newer = format!(
+            "[settings]\nconfig_version = {}\nprofiles = []\n",
+            SUPPORTED_CONFIG_VERSION + 1
+        );
+        fs::write(&path, &newer).unwrap();
+        let mut pwm = PwmGuiData::new();
+
+        assert!(matches!(
+            pwm.load_settings(),
+            Err(PwmConfigError::ConfigTooNew)
+        ));
+        assert!(matches!(
+            pwm.save_settings(),
+            Err(PwmConfigError::ConfigTooNew)
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), newer);
+    }
+
+    #[test]
+    fn is_probably_url_table() {
+        let cases = [
+            ("example.com", true),
+            ("https://example.com/login?next=1", true),
+            ("user@mail.example.com", true),
+            ("192.168.1.1", true),
+            ("[::1]:8080", true),
+            ("http://[::1]/", true),
+            ("localhost:3000", true),
+            ("example.com:8080", true),
+            ("example", false),
+            ("", false),
+            ("   ", false),
+            ("example .com", false),
+            ("example.com:80a", false),
+            ("example.com:", false),
+            ("[::1]:", false),
+            ("example.com.", false),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(is_probably_url(input), expected, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn generation_characters_come_from_the_charset_file() {
+        let _env = env_lock();
+        let home = temp_home("charset-file");
+        fs::write(home.join("charset.txt"), "xyz123\n").unwrap();
+        let mut setting = PWM_DEFAULT.clone();
+        setting.charset_file = Some(String::from("charset.txt"));
+        setting.excluded_characters = String::from("2");
+
+        assert_eq!(generated(generation_characters(&setting)), "xyz13");
+        let password = generate(&setting);
+        assert!(password.chars().all(|c| "xyz13".contains(c)), "{password}");
+
+        setting.charset_file = Some(String::from("missing.txt"));
+        assert!(generation_characters(&setting).is_err());
+        setting.charset_file = None;
+        assert_eq!(
+            generated(generation_characters(&setting)),
+            setting.effective_characters()
+        );
+    }
+
+    #[test]
+    fn first_run_loads_without_errors() {
+        let _env = env_lock();
+        temp_home("first-run");
+        let mut pwm = PwmGuiData::new();
+        assert!(pwm.load_and_validate().is_ok());
+        assert!(pwm.is_first_run());
+        assert!(!pwm.config.settings.get_settings().is_empty());
+    }
+
+    #[test]
+    fn invalid_settings_are_loaded_and_reported() {
+        let _env = env_lock();
+        let home = temp_home("invalid-setting");
+        let mut config = named_settings(&["good"]);
+        let mut bad = PWM_DEFAULT.clone();
+        bad.name = String::from("bad");
+        bad.characters = String::new();
+        config.settings.push_setting(bad);
+        write_config(&config, home.join("passwordmaker.toml").to_str().unwrap()).unwrap();
+        let mut pwm = PwmGuiData::new();
+
+        let errors = match pwm.load_and_validate() {
+            Ok(()) => panic!("the empty charset wasn't reported"),
+            Err(errors) => errors,
+        };
+        assert!(matches!(errors[..], [PwmSettingsError::EmptyCharset]));
+        assert!(!pwm.is_first_run());
+        assert_eq!(setting_names(&pwm), ["good", "bad"]);
+    }
+
+    #[test]
+    fn dated_modifier_appends_the_formatted_date() {
+        let march =
+            NaiveDateTime::parse_from_str("2026-03-14 15:09:26", "%Y-%m-%d %H:%M:%S").unwrap();
+        let april =
+            NaiveDateTime::parse_from_str("2026-04-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let monthly = Some(String::from("%Y-%m"));
+
+        assert_eq!(
+            generated(dated_modifier("mod", &monthly, march)),
+            "mod2026-03"
+        );
+        assert_eq!(generated(dated_modifier("mod", &None, march)), "mod");
+
+        let mut setting = PWM_DEFAULT.clone();
+        setting.modifier_date_format = monthly;
+        let in_march = generated(PwmInputs::from_setting_at(&setting, march).map(|i| i.modifier));
+        let in_april = generated(PwmInputs::from_setting_at(&setting, april).map(|i| i.modifier));
+        assert_ne!(in_march, in_april);
+    }
+
+    #[test]
+    fn invalid_date_format_is_an_error() {
+        let now =
+            NaiveDateTime::parse_from_str("2026-03-14 15:09:26", "%Y-%m-%d %H:%M:%S").unwrap();
+        assert!(matches!(
+            dated_modifier("mod", &Some(String::from("%Q")), now),
+            Err(PwmSettingsError::InvalidDateFormat)
+        ));
+    }
+
+    #[test]
+    fn clones_generate_in_parallel_like_the_original() {
+        let setting = PWM_DEFAULT.clone();
+        let expected = generate(&setting);
+        let (a, b) = std::thread::scope(|scope| {
+            let a = scope.spawn(|| generate(&setting.clone()));
+            let b = scope.spawn(|| generate(&setting.clone()));
+            (a.join().unwrap(), b.join().unwrap())
+        });
+        assert_eq!(a, expected);
+        assert_eq!(b, expected);
+    }
+
+    #[test]
+    fn read_only_never_writes_the_config() {
+        let _env = env_lock();
+        let home = temp_home("read-only");
+        let mut pwm = PwmGuiData::new();
+        pwm.enable_read_only();
+        pwm.config = named_settings(&["unsaved"]);
+        pwm.dirty = true;
+
+        assert!(pwm.save_settings().is_ok());
+        assert!(!home.join("passwordmaker.toml").exists());
+    }
+
+    #[test]
+    fn empty_charset_is_reported_as_such() {
+        let mut setting = PWM_DEFAULT.clone();
+        setting.characters = String::new();
+        assert!(matches!(
+            validate(&setting),
+            Err(PwmSettingsError::EmptyCharset)
+        ));
+        assert!(
+            setting_warnings_for(&setting).contains(&PwmSettingsError::EmptyCharset.to_string())
+        );
+
+        // passphrases don't use the charset
+        setting.passphrase_mode = true;
+        assert!(validate(&setting).is_ok());
+    }
+
+    #[test]
+    fn save_creates_a_missing_config_dir() {
+        let _env = env_lock();
+        let nested = temp_home("nested-home").join("not").join("there");
+        std::env::set_var("XDG_CONFIG_HOME", &nested);
+        let mut pwm = PwmGuiData::new();
+        pwm.config = named_settings(&["saved"]);
+        pwm.dirty = true;
+
+        assert!(pwm.save_settings().is_ok());
+        assert!(nested.join("passwordmaker.toml").is_file());
+    }
+
+    #[test]
+    fn master_quality_boundaries() {
+        let cases = [
+            ("", MasterQuality::Empty),
+            ("abcdef1", MasterQuality::Weak),
+            ("abcdefgh", MasterQuality::Weak),
+            ("abcdefg1", MasterQuality::Ok),
+            ("Passw0rd", MasterQuality::Weak),
+            ("abcdefghijklmN1", MasterQuality::Ok),
+            ("abcdefghijklmno1", MasterQuality::Ok),
+            ("abcdefghijklmnO1", MasterQuality::Strong),
+        ];
+        for (master, expected) in cases {
+            assert_eq!(master_quality(master), expected, "{master:?}");
+        }
+    }
+
+    #[test]
+    fn max_output_length_cuts_the_password_to_a_prefix() {
+        let mut setting = PWM_DEFAULT.clone();
+        setting.password_length = 20;
+        let full = generate(&setting);
+        setting.max_output_length = Some(12);
+        let cut = generate(&setting);
+        assert_eq!(cut.chars().count(), 12);
+        assert!(full.starts_with(&cut), "{cut} is no prefix of {full}");
+
+        // a limit above the length changes nothing
+        setting.max_output_length = Some(30);
+        assert_eq!(generate(&setting), full);
+    }
+
+    #[test]
+    fn save_refuses_a_file_changed_since_loading() {
+        let _env = env_lock();
+        let home = temp_home("concurrent");
+        let path = home.join("passwordmaker.toml");
+        write_config(&named_settings(&["loaded"]), path.to_str().unwrap()).unwrap();
+        let mut pwm = PwmGuiData::new();
+        pwm.load_settings().unwrap();
+
+        // another instance saves in the meantime
+        let later = SystemTime::now() + std::time::Duration::from_secs(60);
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        pwm.dirty = true;
+        assert!(matches!(
+            pwm.save_settings(),
+            Err(PwmConfigError::ConcurrentModification)
+        ));
+    }
+
+    #[test]
+    fn empty_separator_generates_like_plain_prefix_and_suffix() {
+        let mut setting = PWM_DEFAULT.clone();
+        setting.prefix = String::from("pre");
+        setting.suffix = String::from("suf");
+        setting.separator = String::new();
+        let plain = match Pwm::new(
+            HashAlgorithm::Md5,
+            UseLeetWhenGenerating::NotAtAll,
+            &setting.characters,
+            "",
+            "",
+            setting.password_length,
+            "pre",
+            "suf",
+        ) {
+            Ok(pwm) => pwm,
+            Err(error) => panic!("{}", PwmSettingsError::SettingsError { error }),
+        };
+        let expected = generated(
+            plain
+                .generate(String::from("example.com"), String::from("master"))
+                .map_err(|error| PwmSettingsError::GenerationError { error }),
+        );
+        assert_eq!(generate(&setting), expected);
+
+        setting.separator = String::from("-");
+        let separated = generate(&setting);
+        assert!(separated.starts_with("pre-") && separated.ends_with("-suf"));
+    }
+
+    fn kdf(salt: &str) -> Option<KdfConfig> {
+        Some(KdfConfig {
+            iterations: 1000,
+            salt: String::from(salt),
+        })
+    }
+
+    #[test]
+    fn derive_master_is_pbkdf2_hmac_sha256() {
+        // hashlib.pbkdf2_hmac("sha256", b"master", b"salt", 1000, 32).hex()
+        assert_eq!(
+            derive_master(&kdf("salt"), String::from("master")),
+            "d2db687015817a2592d7e65fa64cdfb2d6345c741c37e88f4e0296501475dfd6"
+        );
+        assert_eq!(derive_master(&None, String::from("master")), "master");
+    }
+
+    #[test]
+    fn kdf_changes_the_password_and_stays_stable() {
+        let setting = PWM_DEFAULT.clone();
+        let with_kdf = |salt| {
+            generated(generate_password(
+                &setting,
+                String::from("example.com"),
+                derive_master(&kdf(salt), String::from("master")),
+            ))
+        };
+        assert_ne!(with_kdf("salt"), generate(&setting));
+        assert_eq!(with_kdf("salt"), with_kdf("salt"));
+        assert_ne!(with_kdf("salt"), with_kdf("pepper"));
+    }
+
+    #[test]
+    fn unknown_algorithm_is_reported_and_kept() {
+        let _env = env_lock();
+        let home = temp_home("bogus-algorithm");
+        let mut config = named_settings(&[]);
+        let mut bogus = PWM_DEFAULT.clone();
+        bogus.name = String::from("bogus");
+        bogus.hash_algorithm = String::from("Sha3000");
+        config.settings.push_setting(bogus);
+        write_config(&config, home.join("passwordmaker.toml").to_str().unwrap()).unwrap();
+        let mut pwm = PwmGuiData::new();
+
+        let errors = match pwm.load_and_validate() {
+            Ok(()) => panic!("the unknown algorithm wasn't reported"),
+            Err(errors) => errors,
+        };
+        assert!(matches!(
+            &errors[..],
+            [PwmSettingsError::UnsupportedAlgorithm { name, algorithm }]
+                if name == "bogus" && algorithm == "Sha3000"
+        ));
+        assert_eq!(pwm.get_current_setting_data().hash_algorithm, "Sha3000");
+    }
+
+    #[test]
+    fn a_new_edit_drops_the_redo_history() {
+        let mut pwm = PwmGuiData::new();
+        pwm.add_setting();
+        pwm.add_setting();
+        assert!(pwm.undo());
+        assert_eq!(pwm.config.settings.len(), 1);
+        assert!(pwm.redo());
+        assert_eq!(pwm.config.settings.len(), 2);
+        assert!(pwm.undo());
+        pwm.delete_setting();
+        assert!(!pwm.redo());
+        assert!(pwm.config.settings.is_empty());
+    }
+
+    #[test]
+    fn undo_depth_is_bounded() {
+        let mut pwm = PwmGuiData::new();
+        for _ in 0..UNDO_DEPTH + 5 {
+            pwm.add_setting();
+        }
+        let mut undone = 0;
+        while pwm.undo() {
+            undone += 1;
+        }
+        assert_eq!(undone, UNDO_DEPTH);
+        assert_eq!(pwm.config.settings.len(), 5);
+    }
+
+    #[test]
+    fn unused_username_generates_like_no_username() {
+        let mut setting = PWM_DEFAULT.clone();
+        let without = generate(&setting);
+        setting.username = String::from("someone");
+        let with = generate(&setting);
+        assert_ne!(with, without);
+
+        setting.use_username = false;
+        assert_eq!(generate(&setting), without);
+    }
+
+    #[test]
+    fn prefix_and_suffix_must_leave_a_generated_character() {
+        let mut setting = PWM_DEFAULT.clone();
+        setting.password_length = 6;
+        setting.prefix = String::from("ab");
+        setting.suffix = String::from("cd");
+        setting.separator = String::from("-");
+        // ab- and -cd take all six characters
+        assert!(matches!(
+            validate(&setting),
+            Err(PwmSettingsError::PrefixSuffixTooLong)
+        ));
+        setting.password_length = 7;
+        assert!(validate(&setting).is_ok());
+    }
+
+    #[test]
+    fn every_algorithm_is_supported() {
+        let unsupported = unsupported_algorithms();
+        assert!(unsupported
+            .iter()
+            .all(|name| HashAlgorithm::VARIANTS.contains(&name.as_str())));
+        assert!(unsupported.is_empty(), "{unsupported:?}");
+    }
+
+    #[test]
+    fn force_case_folds_the_generated_password() {
+        let mut setting = PWM_DEFAULT.clone();
+        let plain = generate(&setting);
+        for (mode, expected) in [
+            ("none", plain.clone()),
+            ("upper", plain.to_uppercase()),
+            ("lower", plain.to_lowercase()),
+        ] {
+            setting.force_case = String::from(mode);
+            assert_eq!(generate(&setting), expected, "{mode}");
+        }
+
+        setting.characters = String::from("0123456789");
+        setting.force_case = String::from("none");
+        let digits = generate(&setting);
+        setting.force_case = String::from("upper");
+        assert_eq!(generate(&setting), digits);
+
+        setting.force_case = String::from("shout");
+        assert!(matches!(
+            validate(&setting),
+            Err(PwmSettingsError::InvalidForceCase)
+        ));
+    }
+
+    #[test]
+    fn expand_path_replaces_home_and_variables() {
+        let _env = env_lock();
+        let home = var("HOME");
+        std::env::set_var("HOME", "/home/tester");
+        std::env::set_var("PWM_TEST_DIR", "charsets");
+        std::env::remove_var("PWM_TEST_UNSET");
+
+        assert_eq!(expand_path("~"), "/home/tester");
+        assert_eq!(expand_path("~/a.txt"), "/home/tester/a.txt");
+        assert_eq!(expand_path("a~/b"), "a~/b");
+        assert_eq!(expand_path("$PWM_TEST_DIR/a.txt"), "charsets/a.txt");
+        assert_eq!(expand_path("${PWM_TEST_DIR}x/a.txt"), "charsetsx/a.txt");
+        assert_eq!(expand_path("$PWM_TEST_UNSET/a.txt"), "/a.txt");
+        assert_eq!(expand_path("cost$"), "cost$");
+
+        match home {
+            Ok(home) => std::env::set_var("HOME", home),
+            Err(_) => std::env::remove_var("HOME"),
+        }
+    }
+
+    #[test]
+    fn dropdown_names_and_display_forms_parse() {
+        assert!(enum_names_parse());
+        assert!(matches!(
+            parse_variant::<HashAlgorithm>("hmac-sha256", HashAlgorithm::VARIANTS),
+            Ok(HashAlgorithm::HmacSha256)
+        ));
+        let level = format!(" {} ", LeetLevel::VARIANTS[0].to_uppercase());
+        assert!(matches!(
+            create_use_leet_when_generating("before and after", &level),
+            Ok(UseLeetWhenGenerating::BeforeAndAfter { .. })
+        ));
+        assert!(matches!(
+            create_use_leet_when_generating("Not at all", ""),
+            Ok(UseLeetWhenGenerating::NotAtAll)
+        ));
+    }
+
+    #[test]
+    fn unknown_names_do_not_parse() {
+        assert!(parse_variant::<HashAlgorithm>("Sha3000", HashAlgorithm::VARIANTS).is_err());
+        assert!(parse_variant::<HashAlgorithm>("", HashAlgorithm::VARIANTS).is_err());
+        assert!(matches!(
+            create_use_leet_when_generating("sideways", ""),
+            Err(LeetError::ParseUseLeetError)
+        ));
+        assert!(matches!(
+            create_use_leet_when_generating("Before", "level eleven"),
+            Err(LeetError::ParseLeetLevelError)
+        ));
+    }
+
+    #[test]
+    fn setting_warnings_table() {
+        let base = || {
+            let mut setting = PWM_DEFAULT.clone();
+            setting.hash_algorithm = String::from("Sha256");
+            setting.password_length = WARN_MIN_LENGTH;
+            setting
+        };
+        let cases: [(fn(&mut PwmSetting), &[&str]); 5] = [
+            (|_| {}, &[]),
+            (
+                |s| s.hash_algorithm = String::from("Md5"),
+                &["Md5 is a weak hash algorithm"],
+            ),
+            (
+                |s| s.password_length = WARN_MIN_LENGTH - 1,
+                &["Shorter than 12 characters"],
+            ),
+            (
+                |s| s.characters = String::new(),
+                &["No characters to build the password from"],
+            ),
+            (
+                |s| s.characters = String::from("abcabd"),
+                &["Characters given more than once: ab"],
+            ),
+        ];
+        for (change, expected) in cases {
+            let mut setting = base();
+            change(&mut setting);
+            assert_eq!(setting_warnings_for(&setting), expected);
+        }
+    }
+
+    #[test]
+    fn compressed_config_round_trips() {
+        let _env = env_lock();
+        let home = temp_home("compressed");
+        let mut pwm = PwmGuiData::new();
+        pwm.enable_compression();
+        pwm.config = named_settings(&["packed"]);
+        pwm.save_settings().unwrap();
+
+        let path = home.join("passwordmaker.toml.gz");
+        assert!(fs::read(&path).unwrap().starts_with(&GZIP_MAGIC));
+        assert!(!home.join("passwordmaker.toml").exists());
+
+        let mut reloaded = PwmGuiData::new();
+        reloaded.load_settings().unwrap();
+        assert_eq!(setting_names(&reloaded), ["packed"]);
+    }
+
+    #[test]
+    fn new_settings_start_from_the_template() {
+        let _env = env_lock();
+        let home = temp_home("template");
+        let template = home.join("template.toml");
+        let mut config = named_settings(&["corporate"]);
+        config.settings.add_setting();
+        write_config(&config, template.to_str().unwrap()).unwrap();
+        let _template = set_env("PASSWORDMAKER_TEMPLATE", &template);
+        let mut pwm = PwmGuiData::new();
+        pwm.create_settings();
+        assert_eq!(setting_names(&pwm), ["corporate"]);
+
+        // an invalid first setting is ignored as a whole
+        let mut broken = PwmConfig::new();
+        let mut setting = PWM_DEFAULT.clone();
+        setting.name = String::from("broken");
+        setting.characters = String::new();
+        broken.settings.push_setting(setting);
+        write_config(&broken, template.to_str().unwrap()).unwrap();
+        pwm.create_settings();
+        assert_eq!(setting_names(&pwm), [PWM_DEFAULT.name.as_str()]);
+    }
+
+    #[test]
+    fn charset_bias_is_two_to_the_bits_mod_size() {
+        // expected values from Python's pow(2, bits, size)
+        let cases = [
+            (16, 128, 0),
+            (64, 128, 0),
+            (10, 128, 6),
+            (94, 128, 14),
+            (94, 160, 24),
+            (94, 256, 8),
+            (1, 128, 0),
+            (0, 128, 0),
+        ];
+        for (size, bits, expected) in cases {
+            assert_eq!(charset_bias(size, bits), expected, "{size} at {bits} bits");
+        }
+    }
+
+    #[test]
+    fn turkish_case_pairs_dotted_and_dotless_i() {
+        assert_eq!(fold_charset("iıİI", ForceCase::Upper, true), "İIİI");
+        assert_eq!(fold_charset("iıİI", ForceCase::Lower, true), "iıiı");
+        assert_eq!(fold_charset("iıİI", ForceCase::None, true), "iıİI");
+        // the default mapping keeps i/I as a pair and lowercases İ to i with a dot above
+        assert_eq!(fold_charset("iI", ForceCase::Upper, false), "II");
+        assert_eq!(fold_charset("İ", ForceCase::Lower, false), "i\u{307}");
+        assert_eq!("İ".to_lowercase(), "i\u{307}");
+    }
+
+    #[test]
+    fn corrupt_reload_leaves_exactly_one_default() {
+        let _env = env_lock();
+        let home = temp_home("corrupt-reload");
+        let _template = set_env("PASSWORDMAKER_TEMPLATE", home.join("no-template.toml"));
+        let path = home.join("passwordmaker.toml");
+        write_config(&named_settings(&["one", "two"]), path.to_str().unwrap()).unwrap();
+        let mut pwm = PwmGuiData::new();
+        pwm.load_settings().unwrap();
+        assert_eq!(setting_names(&pwm), ["one", "two"]);
+
+        fs::write(&path, "this is [not toml").unwrap();
+        assert!(pwm.load_settings().is_err());
+        assert_eq!(setting_names(&pwm), [PWM_DEFAULT.name.as_str()]);
+    }
+
+    #[test]
+    fn padded_url_gives_the_same_password_when_trimmed() {
+        let for_url = |setting: &PwmSetting, url: &str| {
+            generated(generate_password(
+                setting,
+                setting.used_text(url),
+                String::from("master"),
+            ))
+        };
+        let mut setting = PWM_DEFAULT.clone();
+        setting.trim_url = true;
+        let clean = for_url(&setting, "https://www.example.com/login");
+        assert_eq!(
+            for_url(&setting, "  https://www.example.com/login \n"),
+            clean
+        );
+    }
+
+    #[test]
+    fn reencode_switches_the_file_and_cleans_up() {
+        let _env = env_lock();
+        let home = temp_home("reencode");
+        let plain = home.join("passwordmaker.toml");
+        let packed = home.join("passwordmaker.toml.gz");
+        write_config(&named_settings(&["kept"]), plain.to_str().unwrap()).unwrap();
+        fs::write(home.join("passwordmaker.toml.bak"), "import backup").unwrap();
+        let mut pwm = PwmGuiData::new();
+
+        pwm.reencode_config(ConfigFormat::Compressed).unwrap();
+        assert!(pwm.compress);
+        assert!(!plain.exists());
+        assert!(fs::read(&packed).unwrap().starts_with(&GZIP_MAGIC));
+        assert!(!home.join("passwordmaker.toml.reencode.bak").exists());
+        assert_eq!(
+            fs::read_to_string(home.join("passwordmaker.toml.bak")).unwrap(),
+            "import backup"
+        );
+        let config = read_config(packed.to_str().unwrap()).unwrap();
+        assert_eq!(config.settings.get_settings()[0].name, "kept");
+
+        // already in that format
+        pwm.reencode_config(ConfigFormat::Compressed).unwrap();
+        assert!(pwm.compress);
+        assert!(packed.exists());
+    }
+
+    #[test]
+    fn failed_reencode_keeps_the_format() {
+        let _env = env_lock();
+        temp_home("reencode-missing");
+        let mut pwm = PwmGuiData::new();
+        assert!(pwm.reencode_config(ConfigFormat::Compressed).is_err());
+        assert!(!pwm.compress);
+
+        pwm.keyring = true;
+        assert!(matches!(
+            pwm.reencode_config(ConfigFormat::Compressed),
+            Err(PwmConfigError::ConfigInKeyring)
+        ));
+        assert!(!pwm.compress);
+    }
+
+    #[test]
+    fn failed_reencode_write_leaves_the_old_file() {
+        let _env = env_lock();
+        let home = temp_home("reencode-blocked");
+        // a directory where the compressed file should go, the write fails
+        fs::create_dir(home.join("passwordmaker.toml.gz")).unwrap();
+        let plain = home.join("passwordmaker.toml");
+        write_config(&named_settings(&["kept"]), plain.to_str().unwrap()).unwrap();
+        let mut pwm = PwmGuiData::new();
+
+        assert!(pwm.reencode_config(ConfigFormat::Compressed).is_err());
+        assert!(!pwm.compress);
+        assert!(!home.join("passwordmaker.toml.reencode.bak").exists());
+        let config = read_config(plain.to_str().unwrap()).unwrap();
+        assert_eq!(config.settings.get_settings()[0].name, "kept");
+    }
+
+    #[test]
+    fn canary_notices_a_changed_algorithm() {
+        let mut pwm = PwmGuiData::new();
+        pwm.config = named_settings(&["plain", "guarded"]);
+        assert!(pwm.set_canary("example.com", "test master").is_ok());
+        assert_eq!(pwm.check_canary(1), Some(true));
+        assert_eq!(pwm.check_canary(0), None);
+
+        let mut changed = pwm.get_current_setting_data().clone();
+        changed.hash_algorithm = String::from("Sha256");
+        pwm.set_current_setting_data(changed);
+        assert_eq!(pwm.check_canary(1), Some(false));
+    }
+
+    #[test]
+    fn diagnostics_leave_out_the_secrets() {
+        let _env = env_lock();
+        let home = temp_home("diagnostics");
+        let mut config = PwmConfig::new();
+        let mut setting = PWM_DEFAULT.clone();
+        setting.name = String::from("diag");
+        setting.username = String::from("SECRETUSER");
+        setting.modifier = String::from("SECRETMOD");
+        setting.canary = Some(Canary {
+            url: String::from("example.com"),
+            test_master: String::from("SECRETMASTER"),
+            expected: String::from("0000"),
+        });
+        config.settings.push_setting(setting);
+        let text = format!(
+            "{}\n[global.master_kdf]\niterations = 1000\nsalt = \"SECRETSALT\"\n",
+            toml::to_string(&config).unwrap()
+        );
+        let mut pwm = PwmGuiData::new();
+        pwm.set_raw_config(&text).unwrap();
+        assert!(pwm.master_kdf().is_some());
+
+        let path = home.join("diagnostics.txt");
+        pwm.write_diagnostics(path.to_str().unwrap()).unwrap();
+        let diagnostics = fs::read_to_string(&path).unwrap();
+        assert!(diagnostics.contains("diag"));
+        for marker in ["SECRETUSER", "SECRETMOD", "SECRETMASTER", "SECRETSALT"] {
+            assert!(!diagnostics.contains(marker), "{marker} in {diagnostics}");
+        }
+    }
+
+    #[test]
+    fn recent_algorithms_come_first_and_once() {
+        let mut pwm = PwmGuiData::new();
+        pwm.config.global.record_algorithm("Sha256");
+        pwm.config.global.record_algorithm("Sha3000");
+        pwm.config.global.record_algorithm("HmacSha1");
+        let algos = pwm.get_hash_algorithms_mru();
+        assert_eq!(algos[..2], ["HmacSha1", "Sha256"]);
+        assert_eq!(algos.len(), HashAlgorithm::VARIANTS.len());
+        for variant in HashAlgorithm::VARIANTS {
+            assert_eq!(
+                algos.iter().filter(|a| *a == variant).count(),
+                1,
+                "{variant}"
+            );
+        }
+    }
+
+    #[test]
+    fn all_url_flags_flip_together() {
+        let mut pwm = PwmGuiData::new();
+        pwm.config = named_settings(&["site"]);
+        for value in [true, false] {
+            pwm.dirty = false;
+            pwm.set_all_url_flags(value);
+            let setting = pwm.get_current_setting_data();
+            assert_eq!(
+                [
+                    setting.use_protocol,
+                    setting.use_subdomain,
+                    setting.use_domain,
+                    setting.use_userinfo,
+                    setting.use_params,
+                ],
+                [value; 5]
+            );
+            assert!(pwm.dirty);
+        }
+    }
+
+    #[test]
+    fn reset_url_flags_leaves_the_hash_inputs_alone() {
+        let mut pwm = PwmGuiData::new();
+        pwm.config = named_settings(&["site"]);
+        let mut setting = pwm.get_current_setting_data().clone();
+        setting.characters = String::from("abc123");
+        setting.hash_algorithm = String::from("Sha256");
+        setting.password_length = 20;
+        setting.modifier = String::from("mod");
+        setting.username = String::from("user");
+        setting.use_protocol = !PWM_DEFAULT.use_protocol;
+        setting.use_subdomain = !PWM_DEFAULT.use_subdomain;
+        setting.use_domain = !PWM_DEFAULT.use_domain;
+        setting.use_userinfo = !PWM_DEFAULT.use_userinfo;
+        setting.use_params = !PWM_DEFAULT.use_params;
+        pwm.set_current_setting_data(setting.clone());
+        pwm.dirty = false;
+
+        pwm.reset_url_flags();
+        setting.use_protocol = PWM_DEFAULT.use_protocol;
+        setting.use_subdomain = PWM_DEFAULT.use_subdomain;
+        setting.use_domain = PWM_DEFAULT.use_domain;
+        setting.use_userinfo = PWM_DEFAULT.use_userinfo;
+        setting.use_params = PWM_DEFAULT.use_params;
+        assert!(pwm.get_current_setting_data() == &setting);
+        assert!(pwm.dirty);
+    }
+
+    #[test]
+    fn length_preset_is_clamped_to_the_maximum() {
+        let mut pwm = PwmGuiData::new();
+        pwm.config = named_settings(&["site"]);
+        assert_eq!(pwm.set_length_preset(12), 12);
+        assert!(pwm.dirty);
+        assert_eq!(
+            pwm.set_length_preset(MAX_PASSWORD_LENGTH + 1),
+            MAX_PASSWORD_LENGTH
+        );
+        assert_eq!(
+            pwm.get_current_setting_data().password_length,
+            MAX_PASSWORD_LENGTH
+        );
+    }
+
+    #[test]
+    fn unset_path_variables_are_load_errors() {
+        let _env = env_lock();
+        let home = temp_home("unset-variable");
+        std::env::remove_var("PWM_TEST_UNSET");
+        let mut config = named_settings(&[]);
+        let mut setting = PWM_DEFAULT.clone();
+        setting.charset_file = Some(String::from("$PWM_TEST_UNSET/chars.txt"));
+        config.settings.push_setting(setting);
+        write_config(&config, home.join("passwordmaker.toml").to_str().unwrap()).unwrap();
+        let mut pwm = PwmGuiData::new();
+
+        let errors = match pwm.load_and_validate() {
+            Ok(()) => panic!("the unset variable wasn't reported"),
+            Err(errors) => errors,
+        };
+        assert!(errors.iter().any(|e| matches!(
+            e,
+            PwmSettingsError::UnsetPathVariable { path, variable }
+                if path == "$PWM_TEST_UNSET/chars.txt" && variable == "PWM_TEST_UNSET"
+        )));
+        assert_eq!(
+            unset_path_variables("a/$PWM_TEST_UNSET"),
+            ["PWM_TEST_UNSET"]
+        );
     }
 }
@@ -0,0 +1,31 @@
+// Torsten Sadowski
+// SPDX-License-Identifier:  GPL-3.0-or-later
+
+use once_cell::sync::Lazy;
+
+// EFF large wordlist, https://www.eff.org/dice, CC BY 3.0 US.
+// 7776 words in dice order, 11111 is the first line and 66666 the last.
+static WORDLIST: Lazy<Vec<&'static str>> =
+    Lazy::new(|| include_str!("eff_large_wordlist.txt").lines().collect());
+
+// In passphrase mode the generator produces dice rolls from this charset,
+// five rolls pick one word just like diceware.
+pub const DICE_CHARACTERS: &str = "123456";
+pub const ROLLS_PER_WORD: usize = 5;
+
+// Turns generator output over DICE_CHARACTERS into words. An incomplete group at
+// the end is dropped, other characters (e.g. from leet) count as a roll of 1.
+pub fn dice_to_passphrase(dice: &str, separator: &str) -> String {
+    let rolls: Vec<usize> = dice
+        .chars()
+        .map(|c| match c.to_digit(10) {
+            Some(d @ 1..=6) => d as usize - 1,
+            _ => 0,
+        })
+        .collect();
+    let words: Vec<&str> = rolls
+        .chunks_exact(ROLLS_PER_WORD)
+        .map(|word| WORDLIST[word.iter().fold(0, |index, roll| index * 6 + roll)])
+        .collect();
+    words.join(separator)
+}
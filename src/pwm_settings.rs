@@ -1,27 +1,96 @@
 // Torsten Sadowski
 // SPDX-License-Identifier:  GPL-3.0-or-later
 
+use crate::pwm_gui_data::{generation_characters, validate, PwmConfigError};
+use chrono::{DateTime, Duration, Local};
 use once_cell::sync::Lazy;
-use passwordmaker_rs::SettingsError;
+use passwordmaker_rs::{GenerationError, ProtocolUsageMode, SettingsError, UrlParsing};
 use serde::{Deserialize, Serialize};
+use sha2::Digest;
 use slint::SharedString;
 use strum::ParseError;
-use strum_macros::Display;
+use strum_macros::{Display, EnumString};
 
+#[derive(Debug, Clone, Copy, Display)]
 pub enum LeetError {
+    #[strum(to_string = "Choose a leet level for this leet mode")]
     ParseLeetLevelError,
+    #[strum(to_string = "Unknown leet mode")]
     ParseUseLeetError,
 }
 
 #[derive(Display)]
 pub enum PwmSettingsError {
     Ok,
-    HashAlgorithmError { error: ParseError },
-    LeetError { error: LeetError },
-    SettingsError { error: SettingsError },
+    HashAlgorithmError {
+        error: ParseError,
+    },
+    LeetError {
+        error: LeetError,
+    },
+    SettingsError {
+        error: SettingsError,
+    },
+    // no characters entered, or all of them excluded
+    #[strum(to_string = "No characters to build the password from")]
+    EmptyCharset,
+    #[strum(to_string = "No setting selected")]
+    NoSettingSelected,
+    ConfigError {
+        error: PwmConfigError,
+    },
+    InvalidDateFormat,
+    #[strum(to_string = "Unknown case, use none, upper or lower")]
+    InvalidForceCase,
+    #[strum(to_string = "Can't read the charset file {path}")]
+    CharsetFileError {
+        path: String,
+    },
+    #[strum(to_string = "Prefix and suffix leave no room for the password")]
+    PrefixSuffixTooLong,
+    #[strum(to_string = "Passphrases need an algorithm that uses the character set")]
+    PassphraseUnsupportedAlgorithm,
+    // e.g. a config written by a newer version, the setting is kept
+    #[strum(to_string = "{name}: unsupported algorithm {algorithm}, please choose another")]
+    UnsupportedAlgorithm {
+        name: String,
+        algorithm: String,
+    },
+    #[strum(to_string = "{error}")]
+    GenerationError {
+        error: GenerationError,
+    },
+    #[strum(to_string = "PASSWORDMAKER_FORCE_ALGORITHM: unknown algorithm {name}, ignored")]
+    UnknownForcedAlgorithm {
+        name: String,
+    },
+    #[strum(to_string = "{path}: ${variable} is not set, expanded to nothing")]
+    UnsetPathVariable {
+        path: String,
+        variable: String,
+    },
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+// A url and a throwaway test master with a hash of the password they gave, to
+// notice when an edit changes what the setting generates. The test master is
+// stored as written, it must not be the real one; the password only as a hash.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Canary {
+    pub url: String,
+    pub test_master: String,
+    pub expected: String,
+}
+
+impl Canary {
+    pub fn hash_password(pw: &str) -> String {
+        sha2::Sha256::digest(pw.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct PwmSetting {
     pub name: String,
     pub hash_algorithm: String,
@@ -38,36 +107,857 @@ pub struct PwmSetting {
     pub use_protocol: bool,
     pub use_params: bool,
     pub use_userinfo: bool,
+    #[serde(default)]
+    pub excluded_characters: String,
+    #[serde(default)]
+    pub notes: String,
+    // chrono format string, the formatted current date is appended to the modifier
+    #[serde(default)]
+    pub modifier_date_format: Option<String>,
+    // ISO-8601 creation time, set once when the setting is added and never changed.
+    // Not part of the Slint struct, settings from before this field stay None.
+    #[serde(default)]
+    pub created: Option<String>,
+    // site limit below password_length, the password is cut to it when generating
+    #[serde(default)]
+    pub max_output_length: Option<usize>,
+    // words from the EFF list instead of characters, see pwm_passphrase
+    #[serde(default)]
+    pub passphrase_mode: bool,
+    #[serde(default = "default_passphrase_words")]
+    pub passphrase_words: usize,
+    #[serde(default = "default_passphrase_separator")]
+    pub passphrase_separator: String,
+    // put between prefix, body and suffix; counts towards the length, so a
+    // non-empty one changes the generated password
+    #[serde(default)]
+    pub separator: String,
+    // text around the password for pasting, see format_output for the placeholders
+    #[serde(default)]
+    pub output_template: Option<String>,
+    // file holding the charset, relative to the config dir, ~ and $VAR expanded;
+    // replaces characters when set
+    #[serde(default)]
+    pub charset_file: Option<String>,
+    // false keeps username for reference only, generation then uses an empty one
+    #[serde(default = "default_use_username")]
+    pub use_username: bool,
+    // "none", "upper" or "lower", applied to the whole finished password. For sites
+    // that ignore case; anything but "none" changes the passwords of the setting.
+    #[serde(default = "default_force_case")]
+    pub force_case: String,
+    // Turkish rules for force_case: i/İ and ı/I are case pairs instead of i/I
+    #[serde(default)]
+    pub turkish_case: bool,
+    // trim spaces and line breaks around a pasted url before parsing. Padded
+    // urls generate as the clean ones only while this is on.
+    #[serde(default = "default_trim_url")]
+    pub trim_url: bool,
+    // remind to change the password this many days after created, None never
+    #[serde(default)]
+    pub rotate_days: Option<u32>,
+    // not part of the Slint struct or the share link, set with set_canary
+    #[serde(default)]
+    pub canary: Option<Canary>,
+    // power users: flags used for generation instead of the use_* ones above,
+    // which then only stay as a note. None generates from the use_* flags.
+    #[serde(default)]
+    pub url_generation_overrides: Option<UrlFlags>,
 }
 
-#[derive(Serialize, Deserialize)]
+fn default_passphrase_words() -> usize {
+    6
+}
+
+fn default_passphrase_separator() -> String {
+    String::from("-")
+}
+
+fn default_use_username() -> bool {
+    true
+}
+
+fn default_trim_url() -> bool {
+    true
+}
+
+fn default_force_case() -> String {
+    String::from("none")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum ForceCase {
+    None,
+    Upper,
+    Lower,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, EnumString)]
+pub enum SettingField {
+    Username,
+    Modifier,
+    Prefix,
+    Suffix,
+    Notes,
+}
+
+// which fields search_settings looks at; All still leaves out the modifier and
+// everything else that feeds the password
+#[derive(Debug, Clone, Copy, PartialEq, EnumString)]
+pub enum SearchScope {
+    Names,
+    All,
+}
+
+// an incoming setting whose name is already taken
+pub struct ImportConflict {
+    pub name: String,
+    pub existing_fingerprint: String,
+    pub incoming_fingerprint: String,
+}
+
+// what to do with one ImportConflict
+#[derive(Debug, Clone, Copy, PartialEq, EnumString)]
+pub enum ConflictDecision {
+    Skip,
+    Rename,
+    Overwrite,
+}
+
+impl SettingField {
+    // everything but notes feeds into the generated password
+    pub fn affects_generation(&self) -> bool {
+        *self != SettingField::Notes
+    }
+}
+
+// A field and its new value for apply_to_indices
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldChange {
+    PasswordLength(usize),
+    HashAlgorithm(String),
+    UseLeet(String),
+    LeetLevel(String),
+    Characters(String),
+    ForceCase(String),
+    Username(String),
+    Modifier(String),
+    Prefix(String),
+    Suffix(String),
+}
+
+impl FieldChange {
+    // field named like the setting field, e.g. "password_length"
+    pub fn parse(field: &str, value: &str) -> Option<FieldChange> {
+        let value = value.to_string();
+        match field {
+            "password_length" => value.parse().ok().map(FieldChange::PasswordLength),
+            "hash_algorithm" => Some(FieldChange::HashAlgorithm(value)),
+            "use_leet" => Some(FieldChange::UseLeet(value)),
+            "leet_level" => Some(FieldChange::LeetLevel(value)),
+            "characters" => Some(FieldChange::Characters(value)),
+            "force_case" => Some(FieldChange::ForceCase(value)),
+            "username" => Some(FieldChange::Username(value)),
+            "modifier" => Some(FieldChange::Modifier(value)),
+            "prefix" => Some(FieldChange::Prefix(value)),
+            "suffix" => Some(FieldChange::Suffix(value)),
+            _ => None,
+        }
+    }
+    fn apply(&self, setting: &mut PwmSetting) {
+        match self {
+            FieldChange::PasswordLength(length) => {
+                setting.password_length = (*length).min(MAX_PASSWORD_LENGTH)
+            }
+            FieldChange::HashAlgorithm(value) => setting.hash_algorithm = value.clone(),
+            FieldChange::UseLeet(value) => setting.use_leet = value.clone(),
+            FieldChange::LeetLevel(value) => setting.leet_level = value.clone(),
+            FieldChange::Characters(value) => setting.characters = value.clone(),
+            FieldChange::ForceCase(value) => setting.force_case = value.clone(),
+            FieldChange::Username(value) => setting.username = value.clone(),
+            FieldChange::Modifier(value) => setting.modifier = value.clone(),
+            FieldChange::Prefix(value) => setting.prefix = value.clone(),
+            FieldChange::Suffix(value) => setting.suffix = value.clone(),
+        }
+    }
+}
+
+// the use_* flags of a setting, on their own so they can be changed for a preview
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct UrlFlags {
+    pub use_protocol: bool,
+    pub use_userinfo: bool,
+    pub use_subdomain: bool,
+    pub use_domain: bool,
+    pub use_params: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, EnumString)]
+pub enum UrlFlag {
+    Protocol,
+    Userinfo,
+    Subdomain,
+    Domain,
+    Params,
+}
+
+impl UrlFlags {
+    pub fn url_parsing(&self) -> UrlParsing {
+        let use_protocol = if self.use_protocol {
+            ProtocolUsageMode::Used
+        } else {
+            ProtocolUsageMode::Ignored
+        };
+        UrlParsing::new(
+            use_protocol,
+            self.use_userinfo,
+            self.use_subdomain,
+            self.use_domain,
+            self.use_params,
+        )
+    }
+    pub fn toggled(mut self, flag: UrlFlag) -> UrlFlags {
+        let value = match flag {
+            UrlFlag::Protocol => &mut self.use_protocol,
+            UrlFlag::Userinfo => &mut self.use_userinfo,
+            UrlFlag::Subdomain => &mut self.use_subdomain,
+            UrlFlag::Domain => &mut self.use_domain,
+            UrlFlag::Params => &mut self.use_params,
+        };
+        *value = !*value;
+        self
+    }
+    // "1" or "0" per flag in the fingerprint's order: domain, subdomain, protocol,
+    // params, userinfo. Used where a single string field is needed.
+    pub fn to_digits(self) -> String {
+        format!(
+            "{}{}{}{}{}",
+            self.use_domain as u8,
+            self.use_subdomain as u8,
+            self.use_protocol as u8,
+            self.use_params as u8,
+            self.use_userinfo as u8
+        )
+    }
+    pub fn from_digits(digits: &str) -> Option<UrlFlags> {
+        let flags: Vec<bool> = digits
+            .chars()
+            .map(|c| match c {
+                '0' => Some(false),
+                '1' => Some(true),
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+        match flags[..] {
+            [use_domain, use_subdomain, use_protocol, use_params, use_userinfo] => Some(UrlFlags {
+                use_protocol,
+                use_userinfo,
+                use_subdomain,
+                use_domain,
+                use_params,
+            }),
+            _ => None,
+        }
+    }
+}
+
+// the text a url turns into with an explicit set of flags instead of a setting's own
+pub fn url_with_flags(url: &str, flags: UrlFlags) -> String {
+    flags.url_parsing().parse(url)
+}
+
+impl PwmSetting {
+    pub fn url_flags(&self) -> UrlFlags {
+        UrlFlags {
+            use_protocol: self.use_protocol,
+            use_userinfo: self.use_userinfo,
+            use_subdomain: self.use_subdomain,
+            use_domain: self.use_domain,
+            use_params: self.use_params,
+        }
+    }
+    // the overrides when set, otherwise the use_* flags
+    pub fn generation_url_flags(&self) -> UrlFlags {
+        self.url_generation_overrides.unwrap_or(self.url_flags())
+    }
+    // url parser for generation, see generation_url_flags
+    pub fn url_parsing(&self) -> UrlParsing {
+        self.generation_url_flags().url_parsing()
+    }
+    // username, modifier, prefix, suffix, notes and the canary replaced by their
+    // length; name and the generation choices are kept
+    pub fn redacted(&self) -> PwmSetting {
+        PwmSetting {
+            username: redact(&self.username),
+            modifier: redact(&self.modifier),
+            prefix: redact(&self.prefix),
+            suffix: redact(&self.suffix),
+            notes: redact(&self.notes),
+            canary: self.canary.as_ref().map(|canary| Canary {
+                url: canary.url.clone(),
+                test_master: redact(&canary.test_master),
+                expected: redact(&canary.expected),
+            }),
+            ..self.clone()
+        }
+    }
+    pub fn rotation_due(&self) -> bool {
+        self.rotation_due_at(Local::now())
+    }
+    // Counted from created, settings without it are never due. So are periods
+    // that end beyond what chrono can represent.
+    pub fn rotation_due_at(&self, now: DateTime<Local>) -> bool {
+        let (days, created) = match (self.rotate_days, &self.created) {
+            (Some(days), Some(created)) => (days, created),
+            _ => return false,
+        };
+        let created = match DateTime::parse_from_rfc3339(created) {
+            Ok(created) => created.with_timezone(&Local),
+            Err(_) => return false,
+        };
+        match Duration::try_days(days.into()).and_then(|period| created.checked_add_signed(period))
+        {
+            Some(due) => now >= due,
+            None => false,
+        }
+    }
+    // the text generation uses for a url
+    pub fn used_text(&self, url: &str) -> String {
+        let url = if self.trim_url { url.trim() } else { url };
+        self.url_parsing().parse(url)
+    }
+    // PasswordMaker cuts the output to the requested length, so a shorter length
+    // yields a prefix of the longer password and results stay deterministic
+    pub fn effective_length(&self) -> usize {
+        match self.max_output_length {
+            Some(max) => self.password_length.min(max),
+            None => self.password_length,
+        }
+    }
+    // characters minus excluded_characters, in their original order
+    pub fn effective_characters(&self) -> String {
+        self.without_excluded(&self.characters)
+    }
+    // the username as the generator sees it
+    pub fn effective_username(&self) -> &str {
+        if self.use_username {
+            &self.username
+        } else {
+            ""
+        }
+    }
+    pub fn without_excluded(&self, characters: &str) -> String {
+        characters
+            .chars()
+            .filter(|c| !self.excluded_characters.contains(*c))
+            .collect()
+    }
+    // Short hash of everything that affects generation. The name is left out
+    // so identical profiles match across machines regardless of what they're called.
+    pub fn fingerprint(&self) -> String {
+        let mut fields = [
+            self.hash_algorithm.as_str(),
+            self.use_leet.as_str(),
+            self.leet_level.as_str(),
+            self.characters.as_str(),
+            self.excluded_characters.as_str(),
+            self.effective_username(),
+            self.modifier.as_str(),
+            &self.effective_length().to_string(),
+            self.prefix.as_str(),
+            self.suffix.as_str(),
+            &self.generation_url_flags().to_digits(),
+        ]
+        .join("\0");
+        // optional fields only count when set, so older fingerprints stay valid
+        if let Some(date_format) = &self.modifier_date_format {
+            fields.push_str("\0modifier_date_format=");
+            fields.push_str(date_format);
+        }
+        if let Some(charset_file) = &self.charset_file {
+            fields.push_str("\0charset_file=");
+            fields.push_str(charset_file);
+        }
+        if !self.separator.is_empty() {
+            fields.push_str("\0separator=");
+            fields.push_str(&self.separator);
+        }
+        if self.force_case != default_force_case() {
+            fields.push_str("\0force_case=");
+            fields.push_str(&self.force_case);
+        }
+        if self.turkish_case {
+            fields.push_str("\0turkish_case=true");
+        }
+        if !self.trim_url {
+            fields.push_str("\0trim_url=false");
+        }
+        if self.passphrase_mode {
+            fields.push_str(&format!(
+                "\0passphrase={}\0{}",
+                self.passphrase_words, self.passphrase_separator
+            ));
+        }
+        sha2::Sha256::digest(fields.as_bytes())
+            .iter()
+            .take(8)
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+    // The modifier acts as a per-profile secret and is never put into the link.
+    pub fn to_share_url(&self) -> String {
+        let params = [
+            ("name", self.name.clone()),
+            ("hash_algorithm", self.hash_algorithm.clone()),
+            ("use_leet", self.use_leet.clone()),
+            ("leet_level", self.leet_level.clone()),
+            ("characters", self.characters.clone()),
+            ("excluded_characters", self.excluded_characters.clone()),
+            ("username", self.username.clone()),
+            ("use_username", self.use_username.to_string()),
+            ("force_case", self.force_case.clone()),
+            ("turkish_case", self.turkish_case.to_string()),
+            ("trim_url", self.trim_url.to_string()),
+            (
+                "rotate_days",
+                self.rotate_days
+                    .map(|days| days.to_string())
+                    .unwrap_or_default(),
+            ),
+            ("password_length", self.password_length.to_string()),
+            (
+                "max_output_length",
+                self.max_output_length
+                    .map(|max| max.to_string())
+                    .unwrap_or_default(),
+            ),
+            ("prefix", self.prefix.clone()),
+            ("suffix", self.suffix.clone()),
+            ("passphrase_mode", self.passphrase_mode.to_string()),
+            ("passphrase_words", self.passphrase_words.to_string()),
+            ("passphrase_separator", self.passphrase_separator.clone()),
+            ("separator", self.separator.clone()),
+            (
+                "modifier_date_format",
+                self.modifier_date_format.clone().unwrap_or_default(),
+            ),
+            (
+                "output_template",
+                self.output_template.clone().unwrap_or_default(),
+            ),
+            ("use_domain", self.use_domain.to_string()),
+            ("use_subdomain", self.use_subdomain.to_string()),
+            ("use_protocol", self.use_protocol.to_string()),
+            ("use_params", self.use_params.to_string()),
+            ("use_userinfo", self.use_userinfo.to_string()),
+            (
+                "url_generation_overrides",
+                self.url_generation_overrides
+                    .map(|flags| flags.to_digits())
+                    .unwrap_or_default(),
+            ),
+        ];
+        let query: Vec<String> = params
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, percent_encode(value)))
+            .collect();
+        format!("{}{}", SHARE_URL_PREFIX, query.join("&"))
+    }
+    // Names of the fields that differ from PWM_DEFAULT. name and created are
+    // left out, they differ for every setting by design.
+    pub fn non_default_fields(&self) -> Vec<String> {
+        let default = &*PWM_DEFAULT;
+        let differs = [
+            (
+                "hash_algorithm",
+                self.hash_algorithm != default.hash_algorithm,
+            ),
+            ("use_leet", self.use_leet != default.use_leet),
+            ("leet_level", self.leet_level != default.leet_level),
+            ("characters", self.characters != default.characters),
+            ("username", self.username != default.username),
+            ("use_username", self.use_username != default.use_username),
+            ("force_case", self.force_case != default.force_case),
+            ("turkish_case", self.turkish_case != default.turkish_case),
+            ("trim_url", self.trim_url != default.trim_url),
+            ("rotate_days", self.rotate_days != default.rotate_days),
+            ("canary", self.canary != default.canary),
+            ("modifier", self.modifier != default.modifier),
+            (
+                "password_length",
+                self.password_length != default.password_length,
+            ),
+            ("prefix", self.prefix != default.prefix),
+            ("suffix", self.suffix != default.suffix),
+            ("use_domain", self.use_domain != default.use_domain),
+            ("use_subdomain", self.use_subdomain != default.use_subdomain),
+            ("use_protocol", self.use_protocol != default.use_protocol),
+            ("use_params", self.use_params != default.use_params),
+            ("use_userinfo", self.use_userinfo != default.use_userinfo),
+            (
+                "url_generation_overrides",
+                self.url_generation_overrides != default.url_generation_overrides,
+            ),
+            (
+                "excluded_characters",
+                self.excluded_characters != default.excluded_characters,
+            ),
+            ("notes", self.notes != default.notes),
+            (
+                "modifier_date_format",
+                self.modifier_date_format != default.modifier_date_format,
+            ),
+            (
+                "max_output_length",
+                self.max_output_length != default.max_output_length,
+            ),
+            (
+                "passphrase_mode",
+                self.passphrase_mode != default.passphrase_mode,
+            ),
+            (
+                "passphrase_words",
+                self.passphrase_words != default.passphrase_words,
+            ),
+            (
+                "passphrase_separator",
+                self.passphrase_separator != default.passphrase_separator,
+            ),
+            ("separator", self.separator != default.separator),
+            (
+                "output_template",
+                self.output_template != default.output_template,
+            ),
+            ("charset_file", self.charset_file != default.charset_file),
+        ];
+        differs
+            .iter()
+            .filter(|(_, differs)| *differs)
+            .map(|(field, _)| String::from(*field))
+            .collect()
+    }
+    // Generation fields as single-quoted shell assignments, one per line.
+    // Like the share link the modifier is left out unless with_secrets is set.
+    pub fn to_env(&self, with_secrets: bool) -> String {
+        let url_flags = self.generation_url_flags();
+        let mut vars = vec![
+            ("PWM_HASH", self.hash_algorithm.clone()),
+            ("PWM_LEET", self.use_leet.clone()),
+            ("PWM_LEET_LEVEL", self.leet_level.clone()),
+            ("PWM_CHARS", self.effective_characters()),
+            ("PWM_USERNAME", self.effective_username().to_string()),
+            ("PWM_LEN", self.effective_length().to_string()),
+            ("PWM_PREFIX", self.prefix.clone()),
+            ("PWM_SUFFIX", self.suffix.clone()),
+            ("PWM_FORCE_CASE", self.force_case.clone()),
+            ("PWM_TURKISH_CASE", self.turkish_case.to_string()),
+            ("PWM_TRIM_URL", self.trim_url.to_string()),
+            ("PWM_SEPARATOR", self.separator.clone()),
+            ("PWM_PASSPHRASE_MODE", self.passphrase_mode.to_string()),
+            ("PWM_PASSPHRASE_WORDS", self.passphrase_words.to_string()),
+            (
+                "PWM_PASSPHRASE_SEPARATOR",
+                self.passphrase_separator.clone(),
+            ),
+            (
+                "PWM_MODIFIER_DATE_FORMAT",
+                self.modifier_date_format.clone().unwrap_or_default(),
+            ),
+            ("PWM_USE_DOMAIN", url_flags.use_domain.to_string()),
+            ("PWM_USE_SUBDOMAIN", url_flags.use_subdomain.to_string()),
+            ("PWM_USE_PROTOCOL", url_flags.use_protocol.to_string()),
+            ("PWM_USE_PARAMS", url_flags.use_params.to_string()),
+            ("PWM_USE_USERINFO", url_flags.use_userinfo.to_string()),
+        ];
+        if with_secrets {
+            vars.push(("PWM_MODIFIER", self.modifier.clone()));
+        }
+        vars.iter()
+            .map(|(key, value)| format!("{}={}\n", key, shell_quote(value)))
+            .collect()
+    }
+    // Fields missing from the link keep their PWM_DEFAULT value, unknown ones are skipped.
+    pub fn from_share_url(url: &str) -> Result<PwmSetting, PwmConfigError> {
+        let query = match url.trim().strip_prefix(SHARE_URL_PREFIX) {
+            Some(query) => query,
+            None => return Err(PwmConfigError::NotAShareUrl),
+        };
+        let mut setting = PWM_DEFAULT.clone();
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, raw) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = match percent_decode(raw) {
+                Some(value) => value,
+                None => return Err(PwmConfigError::ShareUrlEncoding),
+            };
+            let parse_bool = |v: &str| match v.parse::<bool>() {
+                Ok(b) => Ok(b),
+                Err(_) => Err(PwmConfigError::InvalidShareUrl),
+            };
+            match key {
+                "name" => setting.name = value,
+                "hash_algorithm" => setting.hash_algorithm = value,
+                "use_leet" => setting.use_leet = value,
+                "leet_level" => setting.leet_level = value,
+                "characters" => setting.characters = value,
+                "excluded_characters" => setting.excluded_characters = value,
+                "username" => setting.username = value,
+                "use_username" => setting.use_username = parse_bool(&value)?,
+                "force_case" => setting.force_case = value,
+                "turkish_case" => setting.turkish_case = parse_bool(&value)?,
+                "trim_url" => setting.trim_url = parse_bool(&value)?,
+                "rotate_days" if value.is_empty() => setting.rotate_days = None,
+                "rotate_days" => {
+                    setting.rotate_days = match value.parse() {
+                        Ok(days) => Some(days),
+                        Err(_) => return Err(PwmConfigError::InvalidShareUrl),
+                    }
+                }
+                "password_length" => {
+                    setting.password_length = match value.parse() {
+                        Ok(pwl) => pwl,
+                        Err(_) => return Err(PwmConfigError::InvalidShareUrl),
+                    }
+                }
+                "max_output_length" if value.is_empty() => setting.max_output_length = None,
+                "max_output_length" => {
+                    setting.max_output_length = match value.parse() {
+                        Ok(max) => Some(max),
+                        Err(_) => return Err(PwmConfigError::InvalidShareUrl),
+                    }
+                }
+                "prefix" => setting.prefix = value,
+                "passphrase_mode" => setting.passphrase_mode = parse_bool(&value)?,
+                "passphrase_words" => {
+                    setting.passphrase_words = match value.parse() {
+                        Ok(words) => words,
+                        Err(_) => return Err(PwmConfigError::InvalidShareUrl),
+                    }
+                }
+                "passphrase_separator" => setting.passphrase_separator = value,
+                "suffix" => setting.suffix = value,
+                "separator" => setting.separator = value,
+                "modifier_date_format" if value.is_empty() => setting.modifier_date_format = None,
+                "modifier_date_format" => setting.modifier_date_format = Some(value),
+                "output_template" if value.is_empty() => setting.output_template = None,
+                "output_template" => setting.output_template = Some(value),
+                "use_domain" => setting.use_domain = parse_bool(&value)?,
+                "use_subdomain" => setting.use_subdomain = parse_bool(&value)?,
+                "use_protocol" => setting.use_protocol = parse_bool(&value)?,
+                "use_params" => setting.use_params = parse_bool(&value)?,
+                "use_userinfo" => setting.use_userinfo = parse_bool(&value)?,
+                "url_generation_overrides" if value.is_empty() => {
+                    setting.url_generation_overrides = None
+                }
+                "url_generation_overrides" => {
+                    setting.url_generation_overrides = match UrlFlags::from_digits(&value) {
+                        Some(flags) => Some(flags),
+                        None => return Err(PwmConfigError::InvalidShareUrl),
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(setting)
+    }
+}
+
+// True when both settings produce the same password for the same url and master:
+// everything fed to Pwm::new plus the url parsing flags, but not name or notes.
+pub fn generation_equivalent(a: &PwmSetting, b: &PwmSetting) -> bool {
+    a.hash_algorithm == b.hash_algorithm
+        && a.use_leet == b.use_leet
+        && a.leet_level == b.leet_level
+        && a.effective_characters() == b.effective_characters()
+        && a.charset_file == b.charset_file
+        && a.effective_username() == b.effective_username()
+        && a.modifier == b.modifier
+        && a.modifier_date_format == b.modifier_date_format
+        && a.force_case == b.force_case
+        && a.turkish_case == b.turkish_case
+        && a.trim_url == b.trim_url
+        && a.effective_length() == b.effective_length()
+        && a.prefix == b.prefix
+        && a.suffix == b.suffix
+        && a.separator == b.separator
+        && a.generation_url_flags() == b.generation_url_flags()
+        && a.passphrase_mode == b.passphrase_mode
+        && (!a.passphrase_mode
+            || (a.passphrase_words == b.passphrase_words
+                && a.passphrase_separator == b.passphrase_separator))
+}
+
+pub const SHARE_URL_PREFIX: &str = "passwordmaker://setting?";
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+// Single quotes keep everything literal in sh, an embedded ' ends the quoted
+// part, adds an escaped quote and starts a new one.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+// Bump when the file layout changes in a way older binaries can't read.
+// 2: settings moved below [settings], app preferences in [global]
+pub const SUPPORTED_CONFIG_VERSION: u32 = 2;
+
+fn default_config_version() -> u32 {
+    SUPPORTED_CONFIG_VERSION
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PwmSettings {
+    #[serde(default = "default_config_version")]
+    config_version: u32,
     settings: Vec<PwmSetting>,
     current_setting: usize,
 }
 
+// config_version of any toml text, below [settings] as now or at top level as in
+// version 1. None when the text isn't toml or has no version.
+fn config_version_of(text: &str) -> Option<i64> {
+    let value: toml::Value = toml::from_str(text).ok()?;
+    value
+        .get("settings")
+        .and_then(|settings| settings.get("config_version"))
+        .or_else(|| value.get("config_version"))
+        .and_then(toml::Value::as_integer)
+}
+
+// Preferences of the app itself rather than of one setting
+#[derive(Default, Serialize, Deserialize)]
+pub struct GlobalSettings {
+    #[serde(default)]
+    last_page: String,
+    // Off by default. Turning it on changes every generated password, the
+    // site passwords then have to be changed or the option switched off again.
+    #[serde(default)]
+    master_kdf: Option<KdfConfig>,
+    // advanced fields hidden on the settings page. Files from before this field
+    // keep the detailed view, new configs start compact, see PwmConfig::default.
+    #[serde(default)]
+    settings_view_compact: bool,
+    // hash algorithms picked for a setting, latest first, see record_algorithm
+    #[serde(default)]
+    recent_algorithms: Vec<String>,
+}
+
+// how many recent hash algorithms are kept
+const RECENT_ALGORITHMS: usize = 5;
+
+// PBKDF2-HMAC-SHA256 run over the master before it reaches the generator
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KdfConfig {
+    pub iterations: u32,
+    pub salt: String,
+}
+
+// Everything in passwordmaker.toml
+#[derive(Serialize, Deserialize)]
+pub struct PwmConfig {
+    #[serde(default)]
+    pub global: GlobalSettings,
+    pub settings: PwmSettings,
+}
+
+// only the length is left, empty stays empty
+fn redact(secret: &str) -> String {
+    if secret.is_empty() {
+        return String::new();
+    }
+    format!("<redacted, {} chars>", secret.chars().count())
+}
+
+// Layout up to config version 1: the settings collection at top level,
+// with last_page mixed in
+#[derive(Deserialize)]
+struct LegacyPwmSettings {
+    #[serde(flatten)]
+    settings: PwmSettings,
+    #[serde(default)]
+    last_page: String,
+}
+
+// quick buttons on the settings page
+pub const LENGTH_PRESETS: &[usize] = &[8, 12, 16, 20, 24];
+pub const MAX_PASSWORD_LENGTH: usize = 100;
+
+// pages the UI can restore on startup
+pub const PAGE_MAKE: &str = "make";
+pub const PAGE_SETTINGS: &str = "settings";
+
 pub trait PwmSettingsAccess {
     fn new() -> Self;
     fn add_setting(&mut self);
     fn delete_setting(&mut self);
     fn get_current_setting(&self) -> usize;
+    fn selection_state(&self) -> (usize, usize);
     fn set_current_setting(&mut self, current: usize);
     fn get_current_setting_data(&self) -> &PwmSetting;
     fn set_current_setting_data(&mut self, setting: PwmSetting);
     fn get_setting_names(&self) -> Vec<SharedString>;
+    fn get_settings(&self) -> &[PwmSetting];
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool;
+    fn config_version(&self) -> u32;
+    fn unique_name(&self, name: &str) -> String;
+    fn append_settings(&mut self, other: PwmSettings);
+    fn push_setting(&mut self, setting: PwmSetting) -> usize;
+    fn find_by_fingerprint(&self, fp: &str) -> Option<usize>;
+    fn replace_in_field(&mut self, field: SettingField, from: &str, to: &str) -> usize;
+    fn set_password_length(&mut self, length: usize) -> usize;
+    fn apply_to_indices(&mut self, indices: &[usize], change: FieldChange) -> usize;
+    fn settings_compatible_with(&self, forbidden: &str) -> Vec<String>;
+    fn settings_using_algorithm(&self, algo: &str) -> Vec<String>;
+    fn name_exists(&self, name: &str) -> bool;
+    fn search_settings(&self, query: &str, scope: SearchScope) -> Vec<(usize, &'static str)>;
+    fn set_canary(&mut self, canary: Option<Canary>);
+    fn set_all_url_flags(&mut self, value: bool);
+    fn reset_url_flags(&mut self);
+    fn import_conflicts(&self, other: &PwmSettings) -> Vec<ImportConflict>;
+    fn merge_settings(&mut self, other: PwmSettings, decisions: &[ConflictDecision]) -> usize;
 }
 
 impl PwmSettingsAccess for PwmSettings {
     fn new() -> Self {
         let ps = PwmSettings {
+            config_version: SUPPORTED_CONFIG_VERSION,
             settings: Vec::new(),
             current_setting: 0,
         };
         ps
     }
     fn add_setting(&mut self) {
-        self.settings
-            .push(once_cell::sync::Lazy::<PwmSetting>::force(&PWM_DEFAULT).clone());
+        let mut setting = once_cell::sync::Lazy::<PwmSetting>::force(&PWM_DEFAULT).clone();
+        setting.created = Some(Local::now().to_rfc3339());
+        self.settings.push(setting);
         self.current_setting = self.settings.len() - 1;
     }
     fn delete_setting(&mut self) {
@@ -79,17 +969,21 @@ impl PwmSettingsAccess for PwmSettings {
         }
         self.settings.remove(self.current_setting);
         if self.current_setting >= self.settings.len() {
-            self.current_setting = self.settings.len() - 1;
+            self.current_setting = self.settings.len().saturating_sub(1);
         }
     }
     fn get_current_setting(&self) -> usize {
         self.current_setting
     }
+    // (current index, number of settings), read together so they always agree
+    fn selection_state(&self) -> (usize, usize) {
+        (self.current_setting, self.settings.len())
+    }
     fn set_current_setting(&mut self, current: usize) {
         self.current_setting = if current < self.settings.len() {
             current
         } else {
-            self.settings.len() - 1
+            self.settings.len().saturating_sub(1)
         }
     }
     fn get_current_setting_data(&self) -> &PwmSetting {
@@ -100,7 +994,16 @@ impl PwmSettingsAccess for PwmSettings {
     }
     fn set_current_setting_data(&mut self, setting: PwmSetting) {
         match self.settings.get_mut(self.current_setting) {
-            Some(pwms) => *pwms = setting,
+            Some(pwms) => {
+                // the UI doesn't carry the creation time or the canary, keep the stored ones
+                let created = pwms.created.take();
+                let canary = pwms.canary.take();
+                *pwms = PwmSetting {
+                    created,
+                    canary,
+                    ..setting
+                }
+            }
             None => return,
         }
     }
@@ -112,6 +1015,305 @@ impl PwmSettingsAccess for PwmSettings {
                 .map(|s| SharedString::from(s.name.clone())),
         )
     }
+    fn get_settings(&self) -> &[PwmSetting] {
+        &self.settings
+    }
+    fn len(&self) -> usize {
+        self.settings.len()
+    }
+    fn is_empty(&self) -> bool {
+        self.settings.is_empty()
+    }
+    fn config_version(&self) -> u32 {
+        self.config_version
+    }
+    fn unique_name(&self, name: &str) -> String {
+        let taken = |n: &str| self.settings.iter().any(|s| s.name == n);
+        if !taken(name) {
+            return name.to_string();
+        }
+        let mut i = 2;
+        while taken(&format!("{} ({})", name, i)) {
+            i += 1;
+        }
+        format!("{} ({})", name, i)
+    }
+    fn append_settings(&mut self, other: PwmSettings) {
+        for mut setting in other.settings {
+            setting.name = self.unique_name(&setting.name);
+            self.settings.push(setting);
+        }
+    }
+    // renames on a clash and selects the new setting, returns its index
+    fn push_setting(&mut self, mut setting: PwmSetting) -> usize {
+        setting.name = self.unique_name(&setting.name);
+        self.settings.push(setting);
+        self.current_setting = self.settings.len() - 1;
+        self.current_setting
+    }
+    fn find_by_fingerprint(&self, fp: &str) -> Option<usize> {
+        self.settings.iter().position(|s| s.fingerprint() == fp)
+    }
+    fn replace_in_field(&mut self, field: SettingField, from: &str, to: &str) -> usize {
+        if from.is_empty() {
+            return 0;
+        }
+        let mut changed = 0;
+        for setting in self.settings.iter_mut() {
+            let value = match field {
+                SettingField::Username => &mut setting.username,
+                SettingField::Modifier => &mut setting.modifier,
+                SettingField::Prefix => &mut setting.prefix,
+                SettingField::Suffix => &mut setting.suffix,
+                SettingField::Notes => &mut setting.notes,
+            };
+            if value.contains(from) {
+                *value = value.replace(from, to);
+                changed += 1;
+            }
+        }
+        changed
+    }
+    // Settings the change would make unusable are left as they are and not counted,
+    // so are indices past the end. An index given twice counts once.
+    fn apply_to_indices(&mut self, indices: &[usize], change: FieldChange) -> usize {
+        let mut changed = 0;
+        for (i, setting) in self.settings.iter_mut().enumerate() {
+            if !indices.contains(&i) {
+                continue;
+            }
+            let mut updated = setting.clone();
+            change.apply(&mut updated);
+            if validate(&updated).is_ok() {
+                *setting = updated;
+                changed += 1;
+            }
+        }
+        changed
+    }
+    fn set_password_length(&mut self, length: usize) -> usize {
+        let length = length.min(MAX_PASSWORD_LENGTH);
+        if let Some(pwms) = self.settings.get_mut(self.current_setting) {
+            pwms.password_length = length;
+        }
+        length
+    }
+    // a setting only fails a site if every usable character is forbidden there,
+    // or if its charset file can't be read
+    fn settings_compatible_with(&self, forbidden: &str) -> Vec<String> {
+        Vec::from_iter(
+            self.settings
+                .iter()
+                .filter(|s| {
+                    generation_characters(s)
+                        .unwrap_or_default()
+                        .chars()
+                        .any(|c| !forbidden.contains(c))
+                })
+                .map(|s| s.name.clone()),
+        )
+    }
+    // Whole name compared ignoring case, "Sha1" doesn't match "HmacSha1"
+    fn settings_using_algorithm(&self, algo: &str) -> Vec<String> {
+        Vec::from_iter(
+            self.settings
+                .iter()
+                .filter(|s| s.hash_algorithm.eq_ignore_ascii_case(algo))
+                .map(|s| s.name.clone()),
+        )
+    }
+    // Ignores case and the selected setting, so renaming it never clashes with itself.
+    fn name_exists(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.settings
+            .iter()
+            .enumerate()
+            .any(|(i, s)| i != self.current_setting && s.name.to_lowercase() == name)
+    }
+    // Case-insensitive substring match, in settings order. Each index comes once
+    // with the first field that matched: name, username, notes.
+    fn search_settings(&self, query: &str, scope: SearchScope) -> Vec<(usize, &'static str)> {
+        let query = query.to_lowercase();
+        let found = |text: &str| text.to_lowercase().contains(&query);
+        Vec::from_iter(self.settings.iter().enumerate().filter_map(|(i, s)| {
+            if found(&s.name) {
+                return Some((i, "name"));
+            }
+            if scope == SearchScope::Names {
+                return None;
+            }
+            if found(&s.username) {
+                Some((i, "username"))
+            } else if found(&s.notes) {
+                Some((i, "notes"))
+            } else {
+                None
+            }
+        }))
+    }
+    fn set_canary(&mut self, canary: Option<Canary>) {
+        if let Some(pwms) = self.settings.get_mut(self.current_setting) {
+            pwms.canary = canary;
+        }
+    }
+    fn set_all_url_flags(&mut self, value: bool) {
+        if let Some(pwms) = self.settings.get_mut(self.current_setting) {
+            pwms.use_domain = value;
+            pwms.use_subdomain = value;
+            pwms.use_protocol = value;
+            pwms.use_params = value;
+            pwms.use_userinfo = value;
+        }
+    }
+    // only the url flags, everything that feeds the hash stays as it is
+    fn reset_url_flags(&mut self) {
+        if let Some(pwms) = self.settings.get_mut(self.current_setting) {
+            pwms.use_domain = PWM_DEFAULT.use_domain;
+            pwms.use_subdomain = PWM_DEFAULT.use_subdomain;
+            pwms.use_protocol = PWM_DEFAULT.use_protocol;
+            pwms.use_params = PWM_DEFAULT.use_params;
+            pwms.use_userinfo = PWM_DEFAULT.use_userinfo;
+        }
+    }
+    // in the order of other, merge_settings expects its decisions in the same order
+    fn import_conflicts(&self, other: &PwmSettings) -> Vec<ImportConflict> {
+        other
+            .settings
+            .iter()
+            .filter_map(|incoming| {
+                self.settings
+                    .iter()
+                    .find(|existing| existing.name == incoming.name)
+                    .map(|existing| ImportConflict {
+                        name: incoming.name.clone(),
+                        existing_fingerprint: existing.fingerprint(),
+                        incoming_fingerprint: incoming.fingerprint(),
+                    })
+            })
+            .collect()
+    }
+    // Settings without a conflict are appended, conflicts are resolved in order by
+    // decisions, missing decisions skip. Returns the number of settings taken over.
+    fn merge_settings(&mut self, other: PwmSettings, decisions: &[ConflictDecision]) -> usize {
+        let existing = self.settings.len();
+        let mut decisions = decisions.iter();
+        let mut count = 0;
+        for mut setting in other.settings {
+            let conflict = self.settings[..existing]
+                .iter()
+                .position(|s| s.name == setting.name);
+            match conflict {
+                None => {
+                    setting.name = self.unique_name(&setting.name);
+                    self.settings.push(setting);
+                }
+                Some(index) => match decisions.next() {
+                    Some(ConflictDecision::Overwrite) => self.settings[index] = setting,
+                    Some(ConflictDecision::Rename) => {
+                        setting.name = self.unique_name(&setting.name);
+                        self.settings.push(setting);
+                    }
+                    Some(ConflictDecision::Skip) | None => continue,
+                },
+            }
+            count += 1;
+        }
+        count
+    }
+}
+
+impl GlobalSettings {
+    pub fn get_last_page(&self) -> &str {
+        match self.last_page.as_str() {
+            PAGE_SETTINGS => PAGE_SETTINGS,
+            _ => PAGE_MAKE,
+        }
+    }
+    pub fn settings_view_compact(&self) -> bool {
+        self.settings_view_compact
+    }
+    pub fn set_settings_view_compact(&mut self, compact: bool) {
+        self.settings_view_compact = compact;
+    }
+    pub fn master_kdf(&self) -> Option<KdfConfig> {
+        self.master_kdf.clone()
+    }
+    pub fn recent_algorithms(&self) -> &[String] {
+        &self.recent_algorithms
+    }
+    pub fn record_algorithm(&mut self, algo: &str) {
+        self.recent_algorithms.retain(|recent| recent != algo);
+        self.recent_algorithms.insert(0, algo.to_string());
+        self.recent_algorithms.truncate(RECENT_ALGORITHMS);
+    }
+    pub fn set_last_page(&mut self, page: &str) {
+        self.last_page = match page {
+            PAGE_SETTINGS => String::from(PAGE_SETTINGS),
+            _ => String::from(PAGE_MAKE),
+        }
+    }
+}
+
+impl Default for PwmConfig {
+    fn default() -> Self {
+        PwmConfig {
+            global: GlobalSettings {
+                settings_view_compact: true,
+                ..GlobalSettings::default()
+            },
+            settings: PwmSettings::new(),
+        }
+    }
+}
+
+impl PwmConfig {
+    // For bug reports: everything that feeds the password besides the settings
+    // choices is replaced by its length, see PwmSetting::redacted.
+    pub fn redacted(&self) -> PwmConfig {
+        PwmConfig {
+            global: GlobalSettings {
+                last_page: self.global.last_page.clone(),
+                master_kdf: self.global.master_kdf.as_ref().map(|kdf| KdfConfig {
+                    iterations: kdf.iterations,
+                    salt: redact(&kdf.salt),
+                }),
+                settings_view_compact: self.global.settings_view_compact,
+                recent_algorithms: self.global.recent_algorithms.clone(),
+            },
+            settings: PwmSettings {
+                settings: Vec::from_iter(self.settings.settings.iter().map(PwmSetting::redacted)),
+                ..self.settings.clone()
+            },
+        }
+    }
+    pub fn new() -> Self {
+        Self::default()
+    }
+    // Reads the current layout and falls back to the flat one of version 1,
+    // which is wrapped and marked as current so the next save writes the new layout.
+    // A file of a newer version is refused before it is read as PwmConfig, its
+    // layout may no longer parse and must not be mistaken for a broken file.
+    pub fn from_toml(text: &str) -> Result<PwmConfig, PwmConfigError> {
+        if config_version_of(text).is_some_and(|v| v > i64::from(SUPPORTED_CONFIG_VERSION)) {
+            return Err(PwmConfigError::ConfigTooNew);
+        }
+        if let Ok(config) = toml::from_str::<PwmConfig>(text) {
+            return Ok(config);
+        }
+        let legacy: LegacyPwmSettings = match toml::from_str(text) {
+            Ok(legacy) => legacy,
+            Err(_) => return Err(PwmConfigError::Str2Toml),
+        };
+        let mut settings = legacy.settings;
+        settings.config_version = settings.config_version.max(SUPPORTED_CONFIG_VERSION);
+        Ok(PwmConfig {
+            global: GlobalSettings {
+                last_page: legacy.last_page,
+                ..GlobalSettings::default()
+            },
+            settings,
+        })
+    }
 }
 
 pub static PWM_DEFAULT: Lazy<PwmSetting> = Lazy::new(|| {
@@ -130,7 +1332,205 @@ pub static PWM_DEFAULT: Lazy<PwmSetting> = Lazy::new(|| {
     use_subdomain: true,
     use_protocol: false,
     use_params: false,
-    use_userinfo: false
+    use_userinfo: false,
+    excluded_characters: String::from(""),
+    notes: String::from(""),
+    modifier_date_format: None,
+    created: None,
+    max_output_length: None,
+    passphrase_mode: false,
+    passphrase_words: default_passphrase_words(),
+    passphrase_separator: default_passphrase_separator(),
+    separator: String::from(""),
+    output_template: None,
+    charset_file: None,
+    use_username: default_use_username(),
+    force_case: default_force_case(),
+    turkish_case: false,
+    trim_url: default_trim_url(),
+    rotate_days: None,
+    canary: None,
+    url_generation_overrides: None,
     };
     pwm
 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings_with_usernames(usernames: &[&str]) -> PwmSettings {
+        let mut settings = PwmSettings::new();
+        for username in usernames {
+            settings.add_setting();
+            settings.settings.last_mut().unwrap().username = username.to_string();
+        }
+        settings
+    }
+
+    fn usernames(settings: &PwmSettings) -> Vec<&str> {
+        settings
+            .settings
+            .iter()
+            .map(|s| s.username.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn replace_in_field_replaces_partial_and_full_matches() {
+        let mut settings = settings_with_usernames(&["old@mail.com", "old", "other"]);
+        let changed = settings.replace_in_field(SettingField::Username, "old", "new");
+        assert_eq!(changed, 2);
+        assert_eq!(usernames(&settings), ["new@mail.com", "new", "other"]);
+    }
+
+    #[test]
+    fn replace_in_field_ignores_empty_pattern_and_other_fields() {
+        let mut settings = settings_with_usernames(&["old"]);
+        assert_eq!(
+            settings.replace_in_field(SettingField::Username, "", "new"),
+            0
+        );
+        assert_eq!(
+            settings.replace_in_field(SettingField::Notes, "old", "new"),
+            0
+        );
+        assert_eq!(usernames(&settings), ["old"]);
+    }
+
+    #[test]
+    fn generation_equivalent_ignores_notes_but_not_the_algorithm() {
+        let a = PWM_DEFAULT.clone();
+        let mut b = a.clone();
+        b.name = String::from("other name");
+        b.notes = String::from("other notes");
+        assert!(generation_equivalent(&a, &b));
+
+        b.hash_algorithm = String::from("Sha256");
+        assert!(!generation_equivalent(&a, &b));
+    }
+
+    #[test]
+    fn version_1_layout_is_migrated() {
+        let mut settings = settings_with_usernames(&["legacy"]);
+        settings.config_version = 1;
+        let legacy = format!(
+            "last_page = \"settings\"\n{}",
+            toml::to_string(&settings).unwrap()
+        );
+        let config = PwmConfig::from_toml(&legacy).unwrap();
+        assert_eq!(config.global.last_page, PAGE_SETTINGS);
+        assert_eq!(usernames(&config.settings), ["legacy"]);
+        assert_eq!(config.settings.config_version, SUPPORTED_CONFIG_VERSION);
+
+        let value: toml::Value = toml::from_str(&toml::to_string(&config).unwrap()).unwrap();
+        assert!(value.get("last_page").is_none());
+        assert_eq!(value["global"]["last_page"].as_str(), Some(PAGE_SETTINGS));
+        assert_eq!(
+            value["settings"]["config_version"].as_integer(),
+            Some(i64::from(SUPPORTED_CONFIG_VERSION))
+        );
+    }
+
+    #[test]
+    fn current_layout_reads_back() {
+        let mut config = PwmConfig::default();
+        config.global.last_page = String::from(PAGE_SETTINGS);
+        config.settings = settings_with_usernames(&["current"]);
+        let config = PwmConfig::from_toml(&toml::to_string(&config).unwrap()).unwrap();
+        assert_eq!(config.global.last_page, PAGE_SETTINGS);
+        assert!(config.global.settings_view_compact);
+        assert_eq!(usernames(&config.settings), ["current"]);
+    }
+
+    #[test]
+    fn non_default_fields_leave_out_name_and_created() {
+        let mut setting = PWM_DEFAULT.clone();
+        setting.name = String::from("renamed");
+        setting.created = Some(String::from("2026-01-01T00:00:00+00:00"));
+        assert!(setting.non_default_fields().is_empty());
+
+        setting.password_length += 1;
+        setting.notes = String::from("note");
+        assert_eq!(setting.non_default_fields(), ["password_length", "notes"]);
+    }
+
+    const FULL_URL: &str = "https://user@www.example.com/path?x=1";
+
+    fn all_url_flags(setting: &mut PwmSetting, value: bool) {
+        setting.use_protocol = value;
+        setting.use_userinfo = value;
+        setting.use_subdomain = value;
+        setting.use_domain = value;
+        setting.use_params = value;
+    }
+
+    #[test]
+    fn url_overrides_take_priority_for_generation_only() {
+        let mut setting = PWM_DEFAULT.clone();
+        all_url_flags(&mut setting, true);
+        let shown = setting.url_flags();
+        let full = setting.used_text(FULL_URL);
+
+        setting.url_generation_overrides = UrlFlags::from_digits("10000");
+        let mut domain_only = PWM_DEFAULT.clone();
+        all_url_flags(&mut domain_only, false);
+        domain_only.use_domain = true;
+        assert_eq!(setting.used_text(FULL_URL), domain_only.used_text(FULL_URL));
+        assert_ne!(setting.used_text(FULL_URL), full);
+        assert_eq!(setting.url_flags(), shown);
+    }
+
+    #[test]
+    fn no_url_overrides_use_the_flags() {
+        let mut setting = PWM_DEFAULT.clone();
+        setting.url_generation_overrides = None;
+        assert_eq!(setting.generation_url_flags(), setting.url_flags());
+        let plain = setting.used_text(FULL_URL);
+        setting.url_generation_overrides = Some(setting.url_flags());
+        assert_eq!(setting.used_text(FULL_URL), plain);
+    }
+
+    #[test]
+    fn url_flag_digits_round_trip() {
+        for bits in 0..32u8 {
+            let flags = UrlFlags {
+                use_protocol: bits & 1 != 0,
+                use_userinfo: bits & 2 != 0,
+                use_subdomain: bits & 4 != 0,
+                use_domain: bits & 8 != 0,
+                use_params: bits & 16 != 0,
+            };
+            assert_eq!(UrlFlags::from_digits(&flags.to_digits()), Some(flags));
+        }
+        for bad in ["", "1111", "111111", "10a10"] {
+            assert_eq!(UrlFlags::from_digits(bad), None, "{bad:?}");
+        }
+    }
+
+    fn at(rfc3339: &str) -> DateTime<Local> {
+        DateTime::parse_from_rfc3339(rfc3339)
+            .unwrap()
+            .with_timezone(&Local)
+    }
+
+    #[test]
+    fn rotation_is_due_from_the_exact_boundary() {
+        let mut setting = PWM_DEFAULT.clone();
+        setting.created = Some(String::from("2026-01-01T00:00:00+00:00"));
+        setting.rotate_days = Some(30);
+        assert!(setting.rotation_due_at(at("2026-01-31T00:00:00+00:00")));
+        assert!(!setting.rotation_due_at(at("2026-01-30T23:59:59+00:00")));
+
+        setting.rotate_days = None;
+        assert!(!setting.rotation_due_at(at("2100-01-01T00:00:00+00:00")));
+    }
+
+    #[test]
+    fn huge_rotation_period_is_never_due() {
+        let mut setting = PWM_DEFAULT.clone();
+        setting.created = Some(String::from("2026-01-01T00:00:00+00:00"));
+        setting.rotate_days = Some(4_000_000_000);
+        assert!(!setting.rotation_due_at(at("2100-01-01T00:00:00+00:00")));
+    }
+}
@@ -0,0 +1,169 @@
+// Torsten Sadowski
+// SPDX-License-Identifier:  GPL-3.0-or-later
+
+// How often each charset character occurs in a generated password, in charset order.
+// Characters that were never used are listed with 0, which is what shows a charset
+// that is effectively smaller than it looks.
+pub fn character_histogram(password: &str, charset: &str) -> Vec<(char, usize)> {
+    let mut histogram: Vec<(char, usize)> = Vec::new();
+    for c in charset.chars() {
+        if !histogram.iter().any(|(h, _)| *h == c) {
+            histogram.push((c, password.chars().filter(|p| *p == c).count()));
+        }
+    }
+    histogram
+}
+
+// Characters of the password that are not in the charset. The generator should never
+// produce these, prefix and suffix are the usual source.
+pub fn foreign_characters(password: &str, charset: &str) -> Vec<(char, usize)> {
+    let mut foreign: Vec<(char, usize)> = Vec::new();
+    for c in password.chars().filter(|c| !charset.contains(*c)) {
+        match foreign.iter_mut().find(|(f, _)| *f == c) {
+            Some((_, count)) => *count += 1,
+            None => foreign.push((c, 1)),
+        }
+    }
+    foreign
+}
+
+// Characters web forms often reject or mangle: the space gets trimmed, the
+// backslash and the quotes get escaped, <, > and & get HTML encoded.
+pub const RISKY_CHARACTERS: &str = " \\'\"`<>&";
+
+// The risky characters in the charset, each once in charset order. Only a hint,
+// the charset is not changed.
+pub fn risky_charset_chars(charset: &str) -> Vec<char> {
+    let mut risky: Vec<char> = Vec::new();
+    for c in charset.chars().filter(|c| RISKY_CHARACTERS.contains(*c)) {
+        if !risky.contains(&c) {
+            risky.push(c);
+        }
+    }
+    risky
+}
+
+// Upper bound for a password drawn uniformly from the charset. Generated passwords are
+// close to that, prefix and suffix are known to an attacker and don't count.
+pub fn estimate_entropy_bits(length: usize, charset: &str) -> f64 {
+    let mut distinct: Vec<char> = charset.chars().collect();
+    distinct.sort_unstable();
+    distinct.dedup();
+    if distinct.len() < 2 {
+        return 0.0;
+    }
+    length as f64 * (distinct.len() as f64).log2()
+}
+
+// offline attack against a fast hash on a GPU rig
+pub const DEFAULT_GUESSES_PER_SECOND: f64 = 1e10;
+
+// Average time to find the password, half of the search space, in words.
+pub fn crack_time_string(bits: f64, guesses_per_second: f64) -> String {
+    let seconds = 2f64.powf(bits - 1.0) / guesses_per_second;
+    if !seconds.is_finite() {
+        return String::from("forever");
+    }
+    if seconds < 1.0 {
+        return String::from("instant");
+    }
+    const UNITS: &[(f64, &str)] = &[
+        (60.0, "second"),
+        (60.0, "minute"),
+        (24.0, "hour"),
+        (365.25, "day"),
+        (100.0, "year"),
+    ];
+    let mut value = seconds;
+    for (per_next, unit) in UNITS {
+        if value < *per_next {
+            let value = value.floor() as u64;
+            return match value {
+                1 => format!("1 {}", unit),
+                _ => format!("{} {}s", value, unit),
+            };
+        }
+        value /= per_next;
+    }
+    String::from("centuries")
+}
+
+// common charsets by size, smallest first
+pub const CHARSET_SIZES: &[(usize, &str)] = &[
+    (10, "digits"),
+    (26, "lowercase letters"),
+    (36, "lowercase letters and digits"),
+    (62, "letters and digits"),
+    (94, "printable ASCII"),
+];
+
+// larger charsets are not worth suggesting
+pub const MAX_CHARSET_SIZE: usize = 65536;
+
+// Smallest number of distinct characters giving at least bits of entropy at the
+// length, the inverse of estimate_entropy_bits. MAX_CHARSET_SIZE when no charset
+// is large enough, e.g. for length 0.
+pub fn min_charset_size(bits: f64, length: usize) -> usize {
+    if bits <= 0.0 {
+        return 2;
+    }
+    if length == 0 {
+        return MAX_CHARSET_SIZE;
+    }
+    // 2^(64/16) must come out as 16, not 17
+    let size = (2f64.powf(bits / length as f64) - 1e-9).ceil();
+    if !size.is_finite() || size >= MAX_CHARSET_SIZE as f64 {
+        return MAX_CHARSET_SIZE;
+    }
+    (size as usize).max(2)
+}
+
+// The minimal size and the smallest common charset reaching it.
+pub fn min_charset_string(bits: f64, length: usize) -> String {
+    let size = min_charset_size(bits, length);
+    if size >= MAX_CHARSET_SIZE {
+        return String::from("not reachable at this length");
+    }
+    match CHARSET_SIZES.iter().find(|(preset, _)| *preset >= size) {
+        Some((preset, name)) => format!("{} characters, e.g. {} ({})", size, name, preset),
+        None => {
+            let (largest, name) = CHARSET_SIZES[CHARSET_SIZES.len() - 1];
+            format!(
+                "{} characters, more than {} ({}), use a longer password",
+                size, name, largest
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pwm_settings::PWM_DEFAULT;
+
+    #[test]
+    fn crack_time_string_picks_the_unit() {
+        let cases = [
+            (0.0, "instant"),
+            (1.0, "1 second"),
+            (7.0, "1 minute"),
+            (19.0, "3 days"),
+            (70.0, "centuries"),
+            (2000.0, "forever"),
+        ];
+        for (bits, expected) in cases {
+            assert_eq!(crack_time_string(bits, 1.0), expected, "{bits} bits");
+        }
+    }
+
+    #[test]
+    fn risky_charset_chars_in_charset_order() {
+        let risky = risky_charset_chars(&PWM_DEFAULT.characters);
+        assert!(risky.contains(&'\\'));
+        // the default charset has no space
+        assert!(!risky.contains(&' '));
+        assert_eq!(risky_charset_chars("a b\\c"), [' ', '\\']);
+        assert_eq!(risky_charset_chars("<<ab<"), ['<']);
+        assert!(risky_charset_chars("abc123").is_empty());
+    }
+}